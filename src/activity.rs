@@ -0,0 +1,181 @@
+//! Commit activity charts for the repository home page.
+//!
+//! The number of commits per day is computed with a revwalk from the default
+//! branch and cached until it changes. The charts are rendered as inline SVG
+//! so they work without any JavaScript.
+
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use git2::{Oid, Repository};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// how many weeks are shown in the charts, including the current one
+const WEEKS: i64 = 53;
+/// size of a single day in the heatmap, in pixels
+const CELL: i64 = 10;
+/// space between the days in the heatmap, in pixels
+const GAP: i64 = 2;
+/// height of the weekly commit chart, in pixels
+const CHART_HEIGHT: i64 = 60;
+/// how many commits older than the charts are walked before stopping, also
+/// used for the contributors
+pub const MAX_OLD_COMMITS: usize = 1000;
+
+/// Activity of a repository, cached by the path of the repository. The oid is
+/// the commit the default branch pointed to when the activity was computed.
+type ActivityCache = HashMap<PathBuf, (Oid, Arc<Activity>)>;
+static CACHE: Lazy<Mutex<ActivityCache>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+pub struct Activity {
+    /// number of commits per day (in UTC)
+    days: BTreeMap<NaiveDate, usize>,
+}
+
+impl Activity {
+    /// Get the activity for the default branch of the repository, which is only
+    /// computed if the branch changed since the last call.
+    pub fn of(repo: &Repository) -> Arc<Self> {
+        let head = match repo
+            .revparse_single(&crate::default_ref(repo))
+            .and_then(|head| head.peel_to_commit())
+        {
+            Ok(head) => head.id(),
+            // empty repository
+            Err(_) => return Default::default(),
+        };
+
+        if let Some((oid, activity)) = CACHE.lock().unwrap().get(repo.path()) {
+            if *oid == head {
+                return activity.clone();
+            }
+        }
+
+        let activity = Arc::new(Self::compute(repo, head));
+        CACHE
+            .lock()
+            .unwrap()
+            .insert(repo.path().to_path_buf(), (head, activity.clone()));
+        activity
+    }
+
+    fn compute(repo: &Repository, head: Oid) -> Self {
+        // the cached data may still be used a while later, so go back a bit
+        // further than needed
+        let cutoff = (Utc::now() - Duration::weeks(WEEKS + 1)).timestamp();
+
+        let mut days = BTreeMap::new();
+        let mut revwalk = match repo.revwalk() {
+            Ok(revwalk) => revwalk,
+            Err(_) => return Default::default(),
+        };
+        if revwalk.push(head).is_err() {
+            return Default::default();
+        }
+        revwalk.set_sorting(git2::Sort::TIME).unwrap();
+
+        let mut old_commits = 0;
+        for commit in revwalk.filter_map(|oid| repo.find_commit(oid.ok()?).ok()) {
+            let seconds = commit.time().seconds();
+            // the walk is sorted by time, but a commit with a wrong clock can
+            // still be followed by newer ones, so do not stop at the first
+            if seconds < cutoff {
+                old_commits += 1;
+                if old_commits > MAX_OLD_COMMITS {
+                    break;
+                }
+                continue;
+            }
+            let date = chrono::NaiveDateTime::from_timestamp(seconds, 0).date();
+            *days.entry(date).or_insert(0) += 1;
+        }
+
+        Self { days }
+    }
+
+    /// The first day shown in the charts, always a Sunday.
+    fn first_day() -> NaiveDate {
+        let today = Utc::now().date().naive_utc();
+        today
+            - Duration::weeks(WEEKS - 1)
+            - Duration::days(today.weekday().num_days_from_sunday().into())
+    }
+
+    /// Calendar heatmap of the commits per day in the last year.
+    pub fn heatmap_svg(&self) -> String {
+        let first = Self::first_day();
+        let today = Utc::now().date().naive_utc();
+        let max = self
+            .days
+            .range(first..=today)
+            .map(|(_, &n)| n)
+            .max()
+            .unwrap_or(0);
+
+        let mut svg = format!(
+            r#"<svg class="activity-heatmap" width="{}" height="{}" role="img" aria-label="commits per day">"#,
+            WEEKS * (CELL + GAP),
+            7 * (CELL + GAP),
+        );
+        let mut day = first;
+        while day <= today {
+            let count = self.days.get(&day).copied().unwrap_or(0);
+            // scale to one of four levels of activity, like a quartile
+            let level = if count == 0 {
+                0
+            } else {
+                (count * 4).div_ceil(max)
+            };
+            let week = (day - first).num_weeks();
+            svg += &format!(
+                r#"<rect class="activity-{}" x="{}" y="{}" width="{}" height="{}"><title>{} commit{} on {}</title></rect>"#,
+                level,
+                week * (CELL + GAP),
+                i64::from(day.weekday().num_days_from_sunday()) * (CELL + GAP),
+                CELL,
+                CELL,
+                count,
+                if count == 1 { "" } else { "s" },
+                day,
+            );
+            day = day.succ();
+        }
+        svg += "</svg>";
+        svg
+    }
+
+    /// Bar chart of the commits per week in the last year.
+    pub fn weekly_svg(&self) -> String {
+        let first = Self::first_day();
+        let today = Utc::now().date().naive_utc();
+        let mut weeks = [0; WEEKS as usize];
+        // commits with dates in the future are not shown
+        for (day, count) in self.days.range(first..=today) {
+            weeks[(*day - first).num_weeks() as usize] += count;
+        }
+        let max = weeks.iter().copied().max().unwrap_or(0).max(1);
+
+        let mut svg = format!(
+            r#"<svg class="activity-weekly" width="{}" height="{}" role="img" aria-label="commits per week">"#,
+            WEEKS * (CELL + GAP),
+            CHART_HEIGHT,
+        );
+        for (week, &count) in weeks.iter().enumerate() {
+            let height = count as i64 * CHART_HEIGHT / max as i64;
+            svg += &format!(
+                r#"<rect class="activity-bar" x="{}" y="{}" width="{}" height="{}"><title>{} commit{} in the week of {}</title></rect>"#,
+                week as i64 * (CELL + GAP),
+                CHART_HEIGHT - height,
+                CELL,
+                height,
+                count,
+                if count == 1 { "" } else { "s" },
+                first + Duration::weeks(week as i64),
+            );
+        }
+        svg += "</svg>";
+        svg
+    }
+}
@@ -0,0 +1,374 @@
+//! Reading the changed-path Bloom filters of git's commit-graph files.
+//!
+//! `git commit-graph write --changed-paths` stores a Bloom filter of the
+//! paths each commit changed compared to its first parent. If the filter
+//! does not contain a path, the commit definitely did not change it, so the
+//! trees do not have to be compared. See `gitformat-commit-graph(5)` and
+//! git's `bloom.c` for the format.
+
+use git2::{Oid, Repository};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Commit-graphs by the path of the repository, with the modification time
+/// of the file they were read from.
+type GraphCache = HashMap<PathBuf, (Option<SystemTime>, Option<Arc<CommitGraph>>)>;
+static CACHE: Lazy<Mutex<GraphCache>> = Lazy::new(Default::default);
+
+const SEED0: u32 = 0x293a_e76f;
+const SEED1: u32 = 0x7e64_6e2c;
+/// size of a SHA-1 object id
+const OID_SIZE: usize = 20;
+
+/// A single commit-graph file, only the parts needed for the Bloom filters.
+struct GraphFile {
+    data: Vec<u8>,
+    // offsets of the chunks
+    fanout: usize,
+    oids: usize,
+    index: usize,
+    filters: usize,
+    commits: usize,
+}
+
+impl GraphFile {
+    fn parse(data: Vec<u8>) -> Option<Self> {
+        // signature, version 1 and SHA-1
+        if data.get(..6)? != b"CGPH\x01\x01" {
+            return None;
+        }
+        let num_chunks = usize::from(*data.get(6)?);
+
+        let mut chunks = HashMap::new();
+        for i in 0..num_chunks {
+            let entry = data.get(8 + i * 12..8 + (i + 1) * 12)?;
+            let offset = u64::from_be_bytes(entry[4..].try_into().ok()?);
+            chunks.insert(entry[..4].to_vec(), usize::try_from(offset).ok()?);
+        }
+        let chunk = |id: &[u8]| chunks.get(id).copied();
+
+        let fanout = chunk(b"OIDF")?;
+        let commits = read_u32(&data, fanout + 255 * 4)? as usize;
+        let graph = Self {
+            fanout,
+            oids: chunk(b"OIDL")?,
+            // without these the file was written without --changed-paths
+            index: chunk(b"BIDX")?,
+            filters: chunk(b"BDAT")?,
+            commits,
+            data,
+        };
+        // make sure the lookups below stay inside of the file
+        if graph.data.len() < graph.oids + commits * OID_SIZE
+            || graph.data.len() < graph.index + commits * 4
+            || graph.data.len() < graph.filters + 12
+        {
+            return None;
+        }
+        Some(graph)
+    }
+
+    /// Position of the commit in the file, found with a binary search in
+    /// the range of oids with the same first byte.
+    fn position(&self, oid: Oid) -> Option<usize> {
+        let first = usize::from(oid.as_bytes()[0]);
+        let start = match first {
+            0 => 0,
+            _ => read_u32(&self.data, self.fanout + (first - 1) * 4)? as usize,
+        };
+        let end = read_u32(&self.data, self.fanout + first * 4)? as usize;
+
+        let oid_at =
+            |i: usize| &self.data[self.oids + i * OID_SIZE..self.oids + (i + 1) * OID_SIZE];
+        let (mut low, mut high) = (start, end.min(self.commits));
+        while low < high {
+            let mid = (low + high) / 2;
+            match oid_at(mid).cmp(oid.as_bytes()) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Whether the commit might have changed all of the given keys, or `None`
+    /// if the commit is not in this file.
+    fn maybe_contains(&self, oid: Oid, keys: &[Vec<u32>]) -> Option<bool> {
+        let position = self.position(oid)?;
+        let end = read_u32(&self.data, self.index + position * 4)? as usize;
+        let start = match position {
+            0 => 0,
+            _ => read_u32(&self.data, self.index + (position - 1) * 4)? as usize,
+        };
+        // skip the header with the filter settings
+        let filter = self
+            .data
+            .get(self.filters + 12 + start..self.filters + 12 + end)?;
+        if filter.is_empty() {
+            // no filter was computed for this commit
+            return Some(true);
+        }
+
+        let bits = filter.len() as u64 * 8;
+        Some(keys.iter().all(|hashes| {
+            hashes.iter().all(|&hash| {
+                let bit = u64::from(hash) % bits;
+                filter[(bit / 8) as usize] & (1 << (bit % 8)) != 0
+            })
+        }))
+    }
+
+    /// The version of the hash function and the number of hashes per key.
+    fn settings(&self) -> Option<(u32, u32)> {
+        Some((
+            read_u32(&self.data, self.filters)?,
+            read_u32(&self.data, self.filters + 4)?,
+        ))
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// The commit-graph of a repository, which may be split into several files.
+pub struct CommitGraph {
+    files: Vec<GraphFile>,
+}
+
+impl CommitGraph {
+    /// The commit-graph of the repository, if it has one with Bloom filters.
+    pub fn of(repo: &Repository) -> Option<Arc<Self>> {
+        let info = repo.path().join("objects/info");
+        let single = info.join("commit-graph");
+        let chain = info.join("commit-graphs/commit-graph-chain");
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let stamp = modified(&single).max(modified(&chain));
+
+        let mut cache = CACHE.lock().unwrap();
+        if let Some((cached_stamp, graph)) = cache.get(repo.path()) {
+            if *cached_stamp == stamp {
+                return graph.clone();
+            }
+        }
+
+        let paths = match fs::read_to_string(&chain) {
+            Ok(chain) => chain
+                .lines()
+                .map(|hash| info.join(format!("commit-graphs/graph-{}.graph", hash.trim())))
+                .collect(),
+            Err(_) => vec![single],
+        };
+        let files = paths
+            .iter()
+            .filter_map(|path| GraphFile::parse(fs::read(path).ok()?))
+            .collect::<Vec<_>>();
+        // all files need to use the same settings for the keys to match
+        let settings = files.first().and_then(GraphFile::settings);
+        let graph = Some(files)
+            .filter(|files| !files.is_empty())
+            .filter(|files| files.iter().all(|file| file.settings() == settings))
+            .map(|files| Arc::new(Self { files }));
+
+        cache.insert(repo.path().to_path_buf(), (stamp, graph.clone()));
+        graph
+    }
+
+    /// Whether the commit might have changed the path compared to its first
+    /// parent. If this is false, it definitely did not. The filters do not
+    /// contain the root directory, so to check it, check the paths in it.
+    pub fn maybe_changed(&self, commit: Oid, path: &Path) -> bool {
+        match path.to_str().and_then(|path| self.keys(path)) {
+            Some(keys) => self.maybe_changed_keys(commit, &keys),
+            None => true,
+        }
+    }
+
+    /// The keys of a path in the filters, to check many commits for the same
+    /// path. `None` if the path can not be checked.
+    pub fn keys(&self, path: &str) -> Option<PathKeys> {
+        let (version, num_hashes) = self.files[0].settings()?;
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
+            return None;
+        }
+        match version {
+            // version 1 hashes bytes above 0x7f depending on the platform, do
+            // not try to match that
+            1 if path.is_ascii() => {}
+            2 => {}
+            _ => return None,
+        }
+
+        // like git, also check the directories containing the path, which
+        // must also have changed
+        let mut keys = Vec::new();
+        let mut prefix = path;
+        loop {
+            keys.push(key(prefix.as_bytes(), num_hashes));
+            match prefix.rfind('/') {
+                Some(slash) => prefix = &prefix[..slash],
+                None => break,
+            }
+        }
+        Some(PathKeys(keys))
+    }
+
+    /// Like `maybe_changed`, with the keys of the path.
+    pub fn maybe_changed_keys(&self, commit: Oid, keys: &PathKeys) -> bool {
+        self.files
+            .iter()
+            .find_map(|file| file.maybe_contains(commit, &keys.0))
+            .unwrap_or(true)
+    }
+}
+
+/// The keys of a path and the directories containing it.
+pub struct PathKeys(Vec<Vec<u32>>);
+
+/// The hashes of a path in a Bloom filter.
+fn key(path: &[u8], num_hashes: u32) -> Vec<u32> {
+    let hash0 = murmur3(SEED0, path);
+    let hash1 = murmur3(SEED1, path);
+    (0..num_hashes)
+        .map(|i| hash0.wrapping_add(i.wrapping_mul(hash1)))
+        .collect()
+}
+
+/// 32 bit MurmurHash3, as used by git for the Bloom filters.
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut hash = seed;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let k = u32::from_le_bytes(chunk.try_into().unwrap())
+            .wrapping_mul(C1)
+            .rotate_left(15)
+            .wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (i, &byte)| k ^ u32::from(byte) << (8 * i))
+            .wrapping_mul(C1)
+            .rotate_left(15)
+            .wrapping_mul(C2);
+        hash ^= k;
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn murmur3_hashes() {
+        // test vectors of MurmurHash3_x86_32
+        assert_eq!(murmur3(0, b""), 0);
+        assert_eq!(murmur3(1, b""), 0x514e_28b7);
+        assert_eq!(murmur3(0xffff_ffff, b""), 0x81f1_6f39);
+        assert_eq!(murmur3(0, b"\xff\xff\xff\xff"), 0x7629_3b50);
+        assert_eq!(murmur3(0x5082_edee, b"\x21\x43\x65\x87"), 0x2362_f9de);
+        assert_eq!(murmur3(0, b"\x21\x43\x65"), 0x7e4a_8634);
+        assert_eq!(murmur3(0, b"\x21"), 0x7266_1cf4);
+        assert_eq!(
+            murmur3(0, b"The quick brown fox jumps over the lazy dog"),
+            0x2e4f_f723
+        );
+        // version 2 of the filters, version 1 gets 0xb0f6fe38 with a signed
+        // char
+        assert_eq!(murmur3(0, "ü".as_bytes()), 0x7840_e6aa);
+    }
+
+    /// Run git in the directory, with fixed dates so the oids are the same.
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=A", "-c", "user.email=a@example.com"])
+            .args(["-c", "commitGraph.changedPathsVersion=2"])
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_DATE", "2021-01-01T00:00:00Z")
+            .env("GIT_COMMITTER_DATE", "2021-01-01T00:00:00Z")
+            .output()
+            .expect("git is needed for this test");
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn filters_written_by_git() {
+        let dir = std::env::temp_dir().join(format!("mygit-commitgraph-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("dir")).unwrap();
+        git(&dir, &["init", "-q"]);
+
+        let commit = |files: &[&str]| {
+            for file in files {
+                fs::write(dir.join(file), file).unwrap();
+            }
+            git(&dir, &["add", "."]);
+            git(&dir, &["commit", "-q", "-m", "commit"]);
+            Oid::from_str(&git(&dir, &["rev-parse", "HEAD"])).unwrap()
+        };
+        let first = commit(&["a.txt", "dir/b.txt"]);
+        let second = commit(&["dir/c.txt"]);
+        let third = commit(&["dir/ü.txt"]);
+        git(
+            &dir,
+            &["commit-graph", "write", "--reachable", "--changed-paths"],
+        );
+
+        let repo = Repository::open(&dir).unwrap();
+        let graph = CommitGraph::of(&repo).expect("the commit-graph has Bloom filters");
+        let changed = |commit, path: &str| graph.maybe_changed(commit, Path::new(path));
+
+        assert!(changed(first, "a.txt"));
+        assert!(changed(first, "dir"));
+        assert!(changed(first, "dir/b.txt"));
+        assert!(changed(second, "dir/c.txt"));
+        assert!(changed(second, "dir/"));
+        assert!(!changed(second, "a.txt"));
+        assert!(!changed(second, "dir/b.txt"));
+        assert!(!changed(third, "dir/c.txt"));
+        assert!(!changed(first, "dir/c.txt"));
+        // the root is not in the filters
+        assert!(changed(second, ""));
+
+        assert!(changed(third, "dir/ü.txt"));
+        match graph.files[0].settings().unwrap().0 {
+            // hashed with the same bytes
+            2 => assert!(!changed(second, "dir/ü.txt")),
+            // the hashes depend on the platform git ran on, so they are not
+            // checked
+            _ => assert!(changed(second, "dir/ü.txt")),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! Contributors of a repository, similar to `git shortlog -sne`.
+//!
+//! Counting the changed lines needs a diff of every commit, which is slow for
+//! large repositories. The walk stops once the commits are older than the
+//! requested time range, and results are cached by the commit they start
+//! from and the time range.
+
+use git2::{Oid, Repository, Time};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Contributors by the path of the repository, the commit the walk started
+/// from and the time range that was counted.
+type ContributorsCache = LruCache<(PathBuf, Oid, Option<i64>, Option<i64>), Arc<Vec<Contributor>>>;
+static CACHE: Lazy<Mutex<ContributorsCache>> = Lazy::new(|| Mutex::new(LruCache::new(64)));
+
+/// A single line of the contributors page.
+pub struct Contributor {
+    // the identity after applying the mailmap
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+    pub first: Time,
+    pub last: Time,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// The authors of the commits reachable from `head` with a commit time
+/// between `since` and `until` in seconds, both inclusive. Most commits first.
+pub fn of(
+    repo: &Repository,
+    head: Oid,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> Result<Arc<Vec<Contributor>>, git2::Error> {
+    let key = (repo.path().to_path_buf(), head, since, until);
+    if let Some(contributors) = CACHE.lock().unwrap().get(&key) {
+        return Ok(contributors.clone());
+    }
+    let contributors = Arc::new(compute(repo, head, since, until)?);
+    CACHE.lock().unwrap().put(key, contributors.clone());
+    Ok(contributors)
+}
+
+fn compute(
+    repo: &Repository,
+    head: Oid,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> Result<Vec<Contributor>, git2::Error> {
+    let mailmap = crate::load_mailmap(repo)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+
+    let mut contributors = HashMap::<(String, String), Contributor>::new();
+    let mut old_commits = 0;
+    for commit in revwalk.filter_map(|oid| repo.find_commit(oid.ok()?).ok()) {
+        let time = commit.time();
+        if matches!(until, Some(until) if time.seconds() > until) {
+            continue;
+        }
+        // like in the activity, a commit with a wrong clock can be followed
+        // by newer ones, so do not stop at the first old commit
+        if matches!(since, Some(since) if time.seconds() < since) {
+            old_commits += 1;
+            if old_commits > crate::activity::MAX_OLD_COMMITS {
+                break;
+            }
+            continue;
+        }
+
+        // like `git log --numstat`, merges do not count towards line changes
+        let (insertions, deletions) = if commit.parent_count() > 1 {
+            (0, 0)
+        } else {
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let stats = repo
+                .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?
+                .stats()?;
+            (stats.insertions(), stats.deletions())
+        };
+
+        let author = commit.author_with_mailmap(&mailmap)?;
+        let name = author.name().unwrap_or_default().to_string();
+        let email = author.email().unwrap_or_default().to_string();
+        let contributor = contributors
+            .entry((name.clone(), email.clone()))
+            .or_insert_with(|| Contributor {
+                name,
+                email,
+                commits: 0,
+                first: time,
+                last: time,
+                insertions: 0,
+                deletions: 0,
+            });
+        contributor.commits += 1;
+        contributor.first = contributor.first.min(time);
+        contributor.last = contributor.last.max(time);
+        contributor.insertions += insertions;
+        contributor.deletions += deletions;
+    }
+
+    let mut contributors = contributors.into_values().collect::<Vec<_>>();
+    // most commits first, like `git shortlog -n`
+    contributors
+        .sort_unstable_by(|a, b| b.commits.cmp(&a.commits).then_with(|| a.name.cmp(&b.name)));
+    Ok(contributors)
+}
@@ -0,0 +1,180 @@
+//! Dispatching requests to the pages of a repository.
+//!
+//! Repository names can contain slashes, so a route pattern can not tell
+//! where the name ends and the page starts: `/r/log/main/src/tree/x/item/y`
+//! also matches the pattern of the tree view. Instead, the longest prefix of
+//! the path that names a repository is looked up first, and only the rest of
+//! the path is routed to the pages of a repository.
+
+use tide::http::Url;
+use tide::{Endpoint, Middleware, Next, Request};
+
+/// The name of the requested repository as it appears in the URL, i.e.
+/// still percent encoded.
+struct RepoName(String);
+
+/// The URL before the repository name was removed for routing.
+struct OriginalUrl(Url);
+
+/// The name of the repository the request is for.
+pub fn repo_name(req: &Request<()>) -> tide::Result<&str> {
+    req.ext::<RepoName>()
+        .map(|name| name.0.as_str())
+        .ok_or_else(|| tide::Error::from_str(404, "This repository does not exist."))
+}
+
+/// Split the path into the longest prefix for which `exists` returns true and
+/// the rest of the path, which always starts with a slash.
+///
+/// Git LFS appends `.git` to the URL of a remote that does not end with it
+/// to find the LFS API, so for its routes `foo.git` also finds `foo`.
+fn split(path: &str, exists: impl Fn(&str) -> bool) -> Option<(&str, &str)> {
+    let path = path.strip_prefix('/')?;
+    let mut ends = path.match_indices('/').map(|(i, _)| i).collect::<Vec<_>>();
+    ends.push(path.len());
+    ends.into_iter()
+        .rev()
+        .map(|end| path.split_at(end))
+        .find_map(|(name, rest)| {
+            let lfs_name = name
+                .strip_suffix(".git")
+                .filter(|_| rest.starts_with("/info/lfs/"));
+            std::iter::once(name)
+                .chain(lfs_name)
+                .find(|name| !name.is_empty() && exists(name))
+                .map(|name| (name, if rest.is_empty() { "/" } else { rest }))
+        })
+}
+
+/// Routes the pages of a repository, given the routes relative to the
+/// repository, e.g. `/log/:ref`.
+pub struct Dispatch {
+    pages: tide::Server<()>,
+    /// whether a repository with the given name is served
+    exists: fn(&str) -> bool,
+}
+
+impl Dispatch {
+    pub fn new(mut pages: tide::Server<()>, exists: fn(&str) -> bool) -> Self {
+        pages.with(RestoreUrl);
+        Self { pages, exists }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Endpoint<()> for Dispatch {
+    async fn call(&self, mut req: Request<()>) -> tide::Result {
+        let url = req.url().clone();
+        let (name, rest) = split(url.path(), self.exists)
+            .ok_or_else(|| tide::Error::from_str(404, "This repository does not exist."))?;
+        req.set_ext(RepoName(name.to_string()));
+        req.set_ext(OriginalUrl(url.clone()));
+        AsMut::<tide::http::Request>::as_mut(&mut req)
+            .url_mut()
+            .set_path(rest);
+        self.pages.call(req).await
+    }
+}
+
+/// Handlers build links and redirects from the URL of the request, so it is
+/// restored once the page was chosen.
+struct RestoreUrl;
+
+#[tide::utils::async_trait]
+impl Middleware<()> for RestoreUrl {
+    async fn handle(&self, mut req: Request<()>, next: Next<'_, ()>) -> tide::Result {
+        if let Some(OriginalUrl(url)) = req.ext::<OriginalUrl>() {
+            let url = url.clone();
+            *AsMut::<tide::http::Request>::as_mut(&mut req).url_mut() = url;
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exists(name: &str) -> bool {
+        ["r", "team/project.git", "a", "a/log", "lfs"].contains(&name)
+    }
+
+    #[test]
+    fn longest_repository_prefix() {
+        assert_eq!(split("/r", exists), Some(("r", "/")));
+        assert_eq!(split("/r/", exists), Some(("r", "/")));
+        assert_eq!(
+            split("/r/log/main/src/tree/x/item/y", exists),
+            Some(("r", "/log/main/src/tree/x/item/y"))
+        );
+        assert_eq!(
+            split(
+                "/team/project.git/tree/main/item/info/lfs/objects/x",
+                exists
+            ),
+            Some(("team/project.git", "/tree/main/item/info/lfs/objects/x"))
+        );
+        assert_eq!(split("/a/log/main", exists), Some(("a/log", "/main")));
+        assert_eq!(split("/team/other.git/log", exists), None);
+        assert_eq!(split("/", exists), None);
+    }
+
+    #[test]
+    fn lfs_api_of_repository_without_git_suffix() {
+        assert_eq!(
+            split("/lfs.git/info/lfs/objects/batch", exists),
+            Some(("lfs", "/info/lfs/objects/batch"))
+        );
+        assert_eq!(
+            split("/r.git/info/lfs/objects/x", exists),
+            Some(("r", "/info/lfs/objects/x"))
+        );
+        // the name of an existing repository is not changed
+        assert_eq!(
+            split("/team/project.git/info/lfs/objects/x", exists),
+            Some(("team/project.git", "/info/lfs/objects/x"))
+        );
+        // only for the LFS API
+        assert_eq!(split("/lfs.git/log", exists), None);
+    }
+
+    /// The pattern of the page the path is routed to, with some of the
+    /// patterns used in `main`.
+    fn page(path: &str) -> String {
+        let mut pages = tide::new();
+        for pattern in [
+            "/log/:ref/*object_name",
+            "/tree/:ref/item/*object_name",
+            "/info/lfs/objects/:oid",
+        ] {
+            pages.at(pattern).get(move |req: Request<()>| async move {
+                Ok(format!("{} {}", repo_name(&req)?, pattern))
+            });
+        }
+        let mut app = tide::new();
+        app.at("*").all(Dispatch::new(pages, exists));
+
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        async_std::task::block_on(async {
+            let mut res: tide::http::Response =
+                app.respond(tide::http::Request::get(url)).await.unwrap();
+            res.body_string().await.unwrap()
+        })
+    }
+
+    #[test]
+    fn paths_inside_the_repository_are_not_mistaken_for_pages() {
+        assert_eq!(
+            page("/r/log/main/src/tree/x/item/y"),
+            "r /log/:ref/*object_name"
+        );
+        assert_eq!(
+            page("/team/project.git/tree/main/item/info/lfs/objects/x"),
+            "team/project.git /tree/:ref/item/*object_name"
+        );
+        assert_eq!(
+            page("/team/project.git/info/lfs/objects/x"),
+            "team/project.git /info/lfs/objects/:oid"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use git2::{Commit, Repository, Signature, Time};
+use git2::{Commit, Mailmap, Repository, Signature, Time};
 
 pub fn format_datetime(time: &Time, format: &str) -> askama::Result<String> {
     use chrono::{FixedOffset, TimeZone};
@@ -8,6 +8,25 @@ pub fn format_datetime(time: &Time, format: &str) -> askama::Result<String> {
     Ok(datetime.format(format).to_string())
 }
 
+/// How long ago the time was, e.g. "3 days ago".
+pub fn age(time: &Time) -> askama::Result<String> {
+    let seconds = chrono::Utc::now().timestamp() - time.seconds();
+    let (amount, unit) = match seconds {
+        s if s < 60 => return Ok("just now".to_string()),
+        s if s < 60 * 60 => (s / 60, "minute"),
+        s if s < 60 * 60 * 24 => (s / (60 * 60), "hour"),
+        s if s < 60 * 60 * 24 * 30 => (s / (60 * 60 * 24), "day"),
+        s if s < 60 * 60 * 24 * 365 => (s / (60 * 60 * 24 * 30), "month"),
+        s => (s / (60 * 60 * 24 * 365), "year"),
+    };
+    Ok(format!(
+        "{} {}{} ago",
+        amount,
+        unit,
+        if amount == 1 { "" } else { "s" }
+    ))
+}
+
 pub fn unix_perms(m: &i32) -> askama::Result<String> {
     // https://unix.stackexchange.com/questions/450480/file-permission-with-six-bytes-in-git-what-does-it-mean
     // Git doesn’t store arbitrary modes, only a subset of the values are
@@ -24,16 +43,36 @@ pub fn unix_perms(m: &i32) -> askama::Result<String> {
     .into())
 }
 
+/// The path of the repository relative to the project root, which may
+/// contain slashes for repositories in subdirectories.
 pub fn repo_name(repo: &Repository) -> askama::Result<&str> {
-    repo.workdir()
+    let path = repo
+        .workdir()
         // use the path for bare repositories
-        .unwrap_or_else(|| repo.path())
-        .file_name()
-        .unwrap()
-        .to_str()
+        .unwrap_or_else(|| repo.path());
+    path.strip_prefix(&crate::CONFIG.load().root)
+        .ok()
+        .filter(|name| name.components().next().is_some())
+        .or_else(|| path.file_name().map(std::path::Path::new))
+        .and_then(std::path::Path::to_str)
         .ok_or(askama::Error::Fmt(std::fmt::Error))
 }
 
+/// The category of a repository on the index page, which is either set with
+/// the `mygit.section` or `gitweb.category` keys or the directory the
+/// repository is in.
+pub fn category(repo: &Repository) -> askama::Result<String> {
+    Ok(crate::RepoConfig::of(repo)
+        .section
+        .or_else(|| repo.config().ok()?.get_string("gitweb.category").ok())
+        .unwrap_or_else(|| {
+            repo_name(repo)
+                .ok()
+                .and_then(|name| Some(std::path::Path::new(name).parent()?.to_str()?.to_string()))
+                .unwrap_or_default()
+        }))
+}
+
 pub fn description(repo: &Repository) -> askama::Result<String> {
     Ok(std::fs::read_to_string(repo.path().join("description"))
         .unwrap_or_default()
@@ -44,24 +83,91 @@ pub fn description(repo: &Repository) -> askama::Result<String> {
         .to_string())
 }
 
+/// Time of the last commit on HEAD, or on any branch if HEAD does not point
+/// to a commit. Fails for empty repositories.
 pub fn last_modified(repo: &Repository) -> askama::Result<git2::Time> {
-    Ok(repo
-        .head()
-        .unwrap()
-        .peel_to_commit()
-        .unwrap()
-        .committer()
-        .when())
+    repo.head()
+        .and_then(|head| head.peel_to_commit())
+        .map(|commit| commit.committer().when())
+        .ok()
+        .or_else(|| {
+            repo.branches(None)
+                .ok()?
+                .filter_map(|branch| branch.ok()?.0.get().peel_to_commit().ok())
+                .map(|commit| commit.committer().when())
+                .max()
+        })
+        .ok_or(askama::Error::Fmt(std::fmt::Error))
+}
+
+pub fn homepage(repo: &Repository) -> askama::Result<String> {
+    Ok(crate::RepoConfig::of(repo).homepage.unwrap_or_default())
 }
 
+/// The URLs to clone the repository from with their labels. They are set
+/// with `mygit.cloneUrl`, or derived from the configured `clone_urls` or
+/// `clone_base`.
+pub fn clone_urls(repo: &Repository) -> askama::Result<Vec<(String, String)>> {
+    let name = repo_name(repo)?;
+    let repo_urls = crate::RepoConfig::of(repo).clone_urls;
+    if !repo_urls.is_empty() {
+        return Ok(repo_urls
+            .into_iter()
+            .map(|url| {
+                // use the scheme as the label, e.g. https or ssh
+                let label = match url.split_once("://") {
+                    Some((scheme, _)) => scheme.to_string(),
+                    None => "ssh".to_string(),
+                };
+                (label, url)
+            })
+            .collect());
+    }
+
+    let config = crate::CONFIG.load();
+    if config.clone_urls.is_empty() {
+        return Ok(vec![(
+            "clone".to_string(),
+            format!("{}/{}", config.clone_base, name),
+        )]);
+    }
+    Ok(config
+        .clone_urls
+        .iter()
+        .map(|clone_url| {
+            (
+                clone_url.label.clone(),
+                clone_url.url.replace("{repo}", name),
+            )
+        })
+        .collect())
+}
+
+/// The owner from the projects list, or the `gitweb.owner` key.
 pub fn repo_owner(repo: &Repository) -> askama::Result<String> {
+    if let Some(owner) = crate::listed_owner(repo.workdir().unwrap_or_else(|| repo.path())) {
+        return Ok(owner);
+    }
     Ok(repo
         .config()
-        .unwrap()
-        .get_string("gitweb.owner")
+        .and_then(|config| config.get_string("gitweb.owner"))
         .unwrap_or_default())
 }
 
+/// Resolve the signature through the repository's mailmap. libgit2 reads it
+/// from the `.mailmap` file (at HEAD for bare repositories) as well as from
+/// the `mailmap.file` and `mailmap.blob` configuration keys, see
+/// `load_mailmap`.
+pub fn mailmap(signature: &Signature, mailmap: &Mailmap) -> askama::Result<Signature<'static>> {
+    Ok(mailmap
+        .resolve_signature(signature)
+        .unwrap_or_else(|_| signature.to_owned()))
+}
+
+pub fn languages(repo: &Repository) -> askama::Result<std::sync::Arc<crate::languages::Languages>> {
+    Ok(crate::languages::Languages::of(repo))
+}
+
 pub fn signature_email_link(signature: &Signature) -> askama::Result<String> {
     Ok(if let Some(email) = signature.email() {
         format!(
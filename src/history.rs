@@ -0,0 +1,235 @@
+//! Finding the commits that last changed paths.
+//!
+//! Instead of computing diffs, the history is walked comparing the oids of
+//! tree entries, and changed-path Bloom filters of the commit-graph are used
+//! to skip commits that did not change a path when they are available. All
+//! entries of a directory are resolved in a single walk. That result depends
+//! on the history and not only on the directory, which may have been reverted
+//! to an earlier tree, so it is cached by the starting commit and the path.
+
+use crate::commitgraph::CommitGraph;
+use git2::{Commit, Oid, Repository};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// how many results are kept in each of the caches
+const CACHE_SIZE: usize = 1000;
+/// Commit ids by entry name, by repository, starting commit and directory.
+type EntriesCache = LruCache<(PathBuf, Oid, PathBuf), Arc<HashMap<Vec<u8>, Oid>>>;
+static CACHE: Lazy<Mutex<EntriesCache>> = Lazy::new(|| Mutex::new(LruCache::new(CACHE_SIZE)));
+
+/// The last commit that changed a path, by repository, starting commit and
+/// path.
+type LastCommitCache = LruCache<(PathBuf, Oid, PathBuf), Option<Oid>>;
+static LAST_COMMITS: Lazy<Mutex<LastCommitCache>> =
+    Lazy::new(|| Mutex::new(LruCache::new(CACHE_SIZE)));
+
+/// The oid of the object at `path` in the commit, the tree of the commit
+/// itself for an empty path.
+fn path_oid(commit: &Commit, path: &Path) -> Option<Oid> {
+    let tree = commit.tree().ok()?;
+    if path.components().next().is_none() {
+        return Some(tree.id());
+    }
+    tree.get_path(path).ok().map(|entry| entry.id())
+}
+
+/// The commit that last changed the file or directory at `path`, starting
+/// from `commit`. Like `git log -1 -- path`, merges only count if the path
+/// differs from all parents.
+pub fn last_commit_for<'a>(
+    repo: &'a Repository,
+    commit: &Commit,
+    path: &Path,
+) -> Option<Commit<'a>> {
+    let key = (repo.path().to_path_buf(), commit.id(), path.to_path_buf());
+    if let Some(oid) = LAST_COMMITS.lock().unwrap().get(&key) {
+        return repo.find_commit((*oid)?).ok();
+    }
+
+    let oid = find_last_commit(repo, commit, path).unwrap_or_else(|e| {
+        tide::log::warn!("can not walk history of {:?}: {}", repo.path(), e);
+        None
+    });
+    LAST_COMMITS.lock().unwrap().put(key, oid);
+    repo.find_commit(oid?).ok()
+}
+
+fn find_last_commit(
+    repo: &Repository,
+    commit: &Commit,
+    path: &Path,
+) -> Result<Option<Oid>, git2::Error> {
+    // the commit that last changed the path must have set it to this
+    let current = match path_oid(commit, path) {
+        Some(oid) => oid,
+        None => return Ok(None),
+    };
+    let graph = CommitGraph::of(repo);
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(commit.id())?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+
+    for oid in revwalk {
+        let oid = oid?;
+        // the path is the same as in the first parent, so this commit can
+        // not be the one that changed it
+        if graph
+            .as_ref()
+            .is_some_and(|graph| !graph.maybe_changed(oid, path))
+        {
+            continue;
+        }
+
+        let commit = repo.find_commit(oid)?;
+        if path_oid(&commit, path) != Some(current) {
+            // an older version, which was replaced later
+            continue;
+        }
+        if commit
+            .parents()
+            .all(|parent| path_oid(&parent, path) != Some(current))
+        {
+            return Ok(Some(oid));
+        }
+    }
+    Ok(None)
+}
+
+/// The oid of the directory at `path` in the commit, if it is a directory.
+fn dir_oid(commit: &Commit, path: &Path) -> Option<Oid> {
+    let tree = commit.tree().ok()?;
+    if path.components().next().is_none() {
+        return Some(tree.id());
+    }
+    tree.get_path(path)
+        .ok()
+        .filter(|entry| entry.kind() == Some(git2::ObjectType::Tree))
+        .map(|entry| entry.id())
+}
+
+/// The oids of the entries of a tree, by name.
+fn entries(repo: &Repository, dir: Oid) -> Result<HashMap<Vec<u8>, Oid>, git2::Error> {
+    Ok(repo
+        .find_tree(dir)?
+        .iter()
+        .map(|entry| (entry.name_bytes().to_vec(), entry.id()))
+        .collect())
+}
+
+/// The commit that last changed each entry of the directory at `path` in the
+/// tree of `commit`, by the name of the entry.
+pub fn last_commits_for_entries(
+    repo: &Repository,
+    commit: &Commit,
+    path: &Path,
+) -> Arc<HashMap<Vec<u8>, Oid>> {
+    let dir = match dir_oid(commit, path) {
+        Some(dir) => dir,
+        None => return Default::default(),
+    };
+    let key = (repo.path().to_path_buf(), commit.id(), path.to_path_buf());
+    if let Some(commits) = CACHE.lock().unwrap().get(&key) {
+        return commits.clone();
+    }
+
+    let commits = Arc::new(compute(repo, commit, path, dir).unwrap_or_else(|e| {
+        tide::log::warn!("can not walk history of {:?}: {}", repo.path(), e);
+        Default::default()
+    }));
+
+    CACHE.lock().unwrap().put(key, commits.clone());
+    commits
+}
+
+fn compute(
+    repo: &Repository,
+    commit: &Commit,
+    path: &Path,
+    dir: Oid,
+) -> Result<HashMap<Vec<u8>, Oid>, git2::Error> {
+    // the entries that are still looking for a commit, with their current
+    // oid: the commit that last changed an entry must have set it to this
+    let mut remaining = entries(repo, dir)?;
+    let mut found = HashMap::new();
+
+    let graph = CommitGraph::of(repo);
+    // the keys of the entries in the Bloom filters, which do not contain the
+    // root directory itself
+    let keys = graph.as_ref().map(|graph| {
+        remaining
+            .keys()
+            .map(|name| {
+                let keys = match (path.to_str(), std::str::from_utf8(name)) {
+                    (Some(""), Ok(name)) => graph.keys(name),
+                    (Some(dir), Ok(name)) => graph.keys(&format!("{}/{}", dir, name)),
+                    _ => None,
+                };
+                (name.clone(), keys)
+            })
+            .collect::<HashMap<_, _>>()
+    });
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(commit.id())?;
+    revwalk.set_sorting(git2::Sort::TIME)?;
+
+    for oid in revwalk {
+        if remaining.is_empty() {
+            break;
+        }
+        let oid = oid?;
+        // none of the remaining entries changed compared to the first parent
+        if let (Some(graph), Some(keys)) = (&graph, &keys) {
+            let maybe_changed = remaining.keys().any(|name| match keys.get(name) {
+                Some(Some(keys)) => graph.maybe_changed_keys(oid, keys),
+                _ => true,
+            });
+            if !maybe_changed {
+                continue;
+            }
+        }
+        let commit = repo.find_commit(oid)?;
+        let dir = match dir_oid(&commit, path) {
+            Some(dir) => dir,
+            // the directory does not exist in this commit
+            None => continue,
+        };
+        let parent_dirs = commit
+            .parents()
+            .map(|parent| dir_oid(&parent, path))
+            .collect::<Vec<_>>();
+        // nothing in the directory changed compared to one of the parents
+        if parent_dirs.contains(&Some(dir)) {
+            continue;
+        }
+
+        let tree = entries(repo, dir)?;
+        let parent_trees = parent_dirs
+            .into_iter()
+            .map(|dir| dir.and_then(|dir| entries(repo, dir).ok()))
+            .collect::<Vec<_>>();
+        remaining.retain(|name, current| {
+            let oid = tree.get(name);
+            if oid != Some(current) {
+                // an older version of the entry, which was replaced later
+                return true;
+            }
+            // like git log, a merge only changed an entry if it differs
+            // from all parents
+            let changed = parent_trees
+                .iter()
+                .all(|parent| parent.as_ref().and_then(|parent| parent.get(name)) != oid);
+            if changed {
+                found.insert(name.clone(), commit.id());
+            }
+            !changed
+        });
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,236 @@
+//! Breakdown of the languages used in a repository.
+//!
+//! Files are classified by their extension using the syntax definitions that
+//! are also used for highlighting, and weighted by their size. Like GitHub's
+//! linguist, files can be excluded with the `linguist-vendored` and
+//! `linguist-generated` attributes in `.gitattributes` files.
+
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Languages of a repository, cached by the path of the repository. The oid
+/// is the tree the breakdown was computed for.
+type LanguagesCache = HashMap<PathBuf, (Oid, Arc<Languages>)>;
+static CACHE: Lazy<Mutex<LanguagesCache>> = Lazy::new(Default::default);
+
+/// Attributes that exclude a file from the breakdown.
+const EXCLUDING_ATTRIBUTES: [&str; 2] = ["linguist-vendored", "linguist-generated"];
+
+#[derive(Default)]
+pub struct Languages {
+    /// languages and the total size of their files in bytes, largest first
+    sizes: Vec<(String, u64)>,
+    total: u64,
+}
+
+/// A single line of a `.gitattributes` file that sets or unsets one of the
+/// excluding attributes.
+struct AttributeRule {
+    /// directory that contains the `.gitattributes` file
+    base: String,
+    pattern: glob::Pattern,
+    /// whether the pattern applies to the full path or only the file name
+    anchored: bool,
+    attribute: &'static str,
+    value: bool,
+}
+
+impl AttributeRule {
+    fn parse(base: &str, text: &str) -> Vec<Self> {
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let pattern = parts.next().unwrap();
+            // patterns only matching directories never match files
+            if pattern.ends_with('/') {
+                continue;
+            }
+            let anchored = pattern.contains('/');
+            let pattern = match glob::Pattern::new(pattern.trim_start_matches('/')) {
+                Ok(pattern) => pattern,
+                Err(_) => continue,
+            };
+            for attribute in parts {
+                let (name, value) = if let Some(name) = attribute.strip_prefix('-') {
+                    (name, false)
+                } else if let Some((name, value)) = attribute.split_once('=') {
+                    (name, value == "true")
+                } else {
+                    (
+                        attribute.trim_start_matches('!'),
+                        !attribute.starts_with('!'),
+                    )
+                };
+                if let Some(&attribute) = EXCLUDING_ATTRIBUTES.iter().find(|&&a| a == name) {
+                    rules.push(AttributeRule {
+                        base: base.to_string(),
+                        pattern: pattern.clone(),
+                        anchored,
+                        attribute,
+                        value,
+                    });
+                }
+            }
+        }
+        rules
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        match path.strip_prefix(&self.base) {
+            Some(relative) if self.anchored => self.pattern.matches_with(relative, options),
+            Some(relative) => {
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+                self.pattern.matches_with(name, options)
+            }
+            None => false,
+        }
+    }
+}
+
+impl Languages {
+    /// Get the language breakdown for the default branch of the repository,
+    /// which is only computed if the tree changed since the last call.
+    pub fn of(repo: &Repository) -> Arc<Self> {
+        let tree = match repo
+            .revparse_single(&crate::default_ref(repo))
+            .and_then(|head| head.peel_to_tree())
+        {
+            Ok(tree) => tree,
+            // empty repository
+            Err(_) => return Default::default(),
+        };
+
+        if let Some((oid, languages)) = CACHE.lock().unwrap().get(repo.path()) {
+            if *oid == tree.id() {
+                return languages.clone();
+            }
+        }
+
+        let languages = Arc::new(Self::compute(repo, &tree));
+        CACHE
+            .lock()
+            .unwrap()
+            .insert(repo.path().to_path_buf(), (tree.id(), languages.clone()));
+        languages
+    }
+
+    fn compute(repo: &Repository, tree: &git2::Tree) -> Self {
+        let odb = match repo.odb() {
+            Ok(odb) => odb,
+            Err(_) => return Default::default(),
+        };
+
+        // first collect all files, because .gitattributes files might be
+        // visited after files they apply to
+        let mut files = Vec::new();
+        let mut rules = Vec::new();
+        let walked = tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(ObjectType::Blob)
+                || entry.filemode() == crate::symlink::FILEMODE
+            {
+                // not a file or a symlink
+                return TreeWalkResult::Ok;
+            }
+            let name = match entry.name() {
+                Some(name) => name,
+                None => return TreeWalkResult::Ok,
+            };
+            if name == ".gitattributes" {
+                if let Ok(blob) = repo.find_blob(entry.id()) {
+                    let text = String::from_utf8_lossy(blob.content());
+                    rules.extend(AttributeRule::parse(dir, &text));
+                }
+            } else {
+                files.push((format!("{}{}", dir, name), entry.id()));
+            }
+            TreeWalkResult::Ok
+        });
+        if let Err(e) = walked {
+            tide::log::warn!("can not walk tree of {:?}: {}", repo.path(), e);
+            return Default::default();
+        }
+
+        let mut sizes = HashMap::<String, u64>::new();
+        for (path, oid) in files {
+            // the last matching rule wins, like in git
+            let excluded = EXCLUDING_ATTRIBUTES.iter().any(|&attribute| {
+                rules
+                    .iter()
+                    .rev()
+                    .find(|rule| rule.attribute == attribute && rule.matches(&path))
+                    .is_some_and(|rule| rule.value)
+            });
+            if excluded {
+                continue;
+            }
+
+            let path = Path::new(&path);
+            let syntax = path
+                .extension()
+                .and_then(|ext| crate::SYNTAXES.find_syntax_by_extension(ext.to_str()?))
+                // some syntaxes list full file names, e.g. Makefile
+                .or_else(|| crate::SYNTAXES.find_syntax_by_extension(path.file_name()?.to_str()?));
+            let syntax = match syntax {
+                Some(syntax) if syntax.name != "Plain Text" => syntax,
+                _ => continue,
+            };
+            if let Ok((size, _)) = odb.read_header(oid) {
+                *sizes.entry(syntax.name.clone()).or_insert(0) += size as u64;
+            }
+        }
+
+        let total = sizes.values().sum();
+        let mut sizes = sizes.into_iter().collect::<Vec<_>>();
+        sizes.sort_unstable_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+        Self { sizes, total }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Languages with their share of the repository in percent, largest first.
+    pub fn percentages(&self) -> Vec<(&str, f64)> {
+        self.sizes
+            .iter()
+            .map(|(name, size)| (name.as_str(), *size as f64 * 100.0 / self.total as f64))
+            .collect()
+    }
+
+    /// Stacked bar showing the share of each language.
+    pub fn bar_html(&self) -> String {
+        let mut html = String::from(r#"<div class="language-bar">"#);
+        for (name, percent) in self.percentages() {
+            html += &format!(
+                r#"<span style="width: {:.2}%; background-color: {}" title="{} {:.1}%"></span>"#,
+                percent,
+                color(name),
+                name,
+                percent,
+            );
+        }
+        html += "</div>";
+        html
+    }
+}
+
+/// A stable color for a language, derived from its name.
+pub fn color(name: &str) -> String {
+    // FNV-1a, so the color does not change between versions of Rust
+    let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    });
+    format!("hsl({}, 55%, 50%)", hash % 360)
+}
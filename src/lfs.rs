@@ -0,0 +1,208 @@
+//! Git LFS: pointer files and the objects they point to.
+//!
+//! Objects are looked up in `lfs/objects/` of the repository directory, where
+//! `git lfs fetch --all` stores them. The batch API only supports downloads,
+//! see <https://github.com/git-lfs/git-lfs/blob/main/docs/api/batch.md>.
+
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tide::http::mime;
+use tide::{Body, Request, Response};
+
+/// pointer files are small, larger blobs are not checked
+pub const MAX_POINTER_SIZE: usize = 1024;
+/// media type of requests and responses of the LFS API
+const LFS_MIME: &str = "application/vnd.git-lfs+json";
+
+/// The contents of an LFS pointer file.
+pub struct Pointer {
+    /// hex encoded SHA-256 of the object
+    pub oid: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Parse the contents of a blob if it is a pointer file.
+    pub fn parse(content: &[u8]) -> Option<Self> {
+        if content.len() > MAX_POINTER_SIZE {
+            return None;
+        }
+        let text = std::str::from_utf8(content).ok()?;
+        let mut lines = text.lines();
+        if !lines
+            .next()?
+            .starts_with("version https://git-lfs.github.com/spec/")
+        {
+            return None;
+        }
+
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            if let Some(value) = line.strip_prefix("oid sha256:") {
+                oid = Some(value.to_string()).filter(|oid| valid_oid(oid));
+            } else if let Some(value) = line.strip_prefix("size ") {
+                size = value.parse().ok();
+            }
+        }
+        Some(Self {
+            oid: oid?,
+            size: size?,
+        })
+    }
+
+    /// The path of the object in the repository, if it is available.
+    pub fn object(&self, repo: &Repository) -> Option<PathBuf> {
+        object_path(repo, &self.oid)
+    }
+}
+
+/// Whether the oid is a SHA-256 in hex, which also makes sure it can be used
+/// in paths.
+fn valid_oid(oid: &str) -> bool {
+    oid.len() == 64 && oid.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The path of an object in the repository, if it is available.
+fn object_path(repo: &Repository, oid: &str) -> Option<PathBuf> {
+    if !valid_oid(oid) {
+        return None;
+    }
+    let path = repo
+        .path()
+        .join("lfs/objects")
+        .join(&oid[..2])
+        .join(&oid[2..4])
+        .join(oid);
+    Some(path).filter(|path| path.is_file())
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    operation: String,
+    #[serde(default)]
+    transfers: Vec<String>,
+    objects: Vec<ObjectSpec>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ObjectSpec {
+    oid: String,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct BatchResponse {
+    transfer: &'static str,
+    objects: Vec<ObjectResponse>,
+}
+
+#[derive(Serialize)]
+struct ObjectResponse {
+    #[serde(flatten)]
+    object: ObjectSpec,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actions: Option<Actions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ObjectError>,
+}
+
+#[derive(Serialize)]
+struct Actions {
+    download: Link,
+}
+
+#[derive(Serialize)]
+struct Link {
+    href: String,
+}
+
+#[derive(Serialize)]
+struct ErrorMessage<'a> {
+    message: &'a str,
+}
+
+#[derive(Serialize)]
+struct ObjectError {
+    code: u16,
+    message: &'static str,
+}
+
+/// An error response of the LFS API, which clients show to the user.
+fn lfs_error(status: u16, message: &str) -> tide::Result {
+    Ok(Response::builder(status)
+        .body(Body::from_json(&ErrorMessage { message })?)
+        .content_type(LFS_MIME)
+        .build())
+}
+
+/// `POST /:repo_name/info/lfs/objects/batch`, tells clients where to download
+/// the objects they ask for.
+pub async fn batch(mut req: Request<()>) -> tide::Result {
+    let batch: BatchRequest = match req.body_json().await {
+        Ok(batch) => batch,
+        Err(_) => return lfs_error(422, "The request is not a valid batch request."),
+    };
+    let repo = crate::repo_from_request(crate::dispatch::repo_name(&req)?)?;
+
+    if batch.operation != "download" {
+        return lfs_error(403, "This server does not accept uploads.");
+    }
+    if !batch.transfers.is_empty() && !batch.transfers.iter().any(|t| t == "basic") {
+        return lfs_error(422, "Only the basic transfer adapter is supported.");
+    }
+
+    let objects = batch
+        .objects
+        .into_iter()
+        .map(|object| {
+            let found = object_path(&repo, &object.oid);
+            let mut href = req.url().clone();
+            href.path_segments_mut().unwrap().pop().push(&object.oid);
+            ObjectResponse {
+                actions: found.as_ref().map(|_| Actions {
+                    download: Link {
+                        href: href.to_string(),
+                    },
+                }),
+                error: match found {
+                    Some(_) => None,
+                    None => Some(ObjectError {
+                        code: 404,
+                        message: "Object does not exist",
+                    }),
+                },
+                object,
+            }
+        })
+        .collect();
+
+    Ok(Response::builder(200)
+        .body(Body::from_json(&BatchResponse {
+            transfer: "basic",
+            objects,
+        })?)
+        .content_type(LFS_MIME)
+        .build())
+}
+
+/// `GET /:repo_name/info/lfs/objects/:oid`, the download action of the batch
+/// API.
+pub async fn download(req: Request<()>) -> tide::Result {
+    // the repository can not be kept while waiting
+    let path = object_path(
+        &crate::repo_from_request(crate::dispatch::repo_name(&req)?)?,
+        req.param("oid")?,
+    );
+    match path {
+        Some(path) => {
+            crate::raw::respond(
+                &req,
+                crate::raw::Source::File(path),
+                Some(mime::BYTE_STREAM),
+            )
+            .await
+        }
+        None => Err(tide::Error::from_str(404, "This object does not exist.")),
+    }
+}
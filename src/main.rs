@@ -1,11 +1,17 @@
+use arc_swap::ArcSwap;
 use askama::Template;
-use git2::{Commit, Diff, DiffOptions, Reference, Repository, Signature, Tag, Tree};
-use once_cell::sync::Lazy;
-use serde::Deserialize;
+use git2::{Commit, Diff, DiffOptions, Mailmap, Reference, Repository, Signature, Tag, Tree};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::{self, File};
 use std::io::Read;
-use std::path::Path;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use syntect::{
     html::{ClassStyle, ClassedHTMLGenerator},
     parsing::SyntaxSet,
@@ -14,13 +20,42 @@ use syntect::{
 
 use tide::{http, Request, Response};
 
+mod activity;
+mod commitgraph;
+mod contributors;
+mod dispatch;
 mod errorpage;
 mod filters;
-
-#[derive(Deserialize, Debug)]
+mod history;
+mod languages;
+mod lfs;
+mod metadata;
+mod raw;
+mod search;
+mod sitesearch;
+mod submodule;
+mod symlink;
+mod tls;
+
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     #[serde(default = "defaults::port")]
     port: u16,
+    // addresses to listen on instead of port, either host and port or the
+    // path of a Unix socket prefixed with `unix:`
+    #[serde(default)]
+    listen: Vec<String>,
+    // permissions of Unix sockets, in octal
+    #[serde(default = "defaults::socket_mode")]
+    socket_mode: String,
+    // PEM files of the certificate chain and private key, to serve HTTPS
+    #[serde(default)]
+    tls_cert: Option<String>,
+    #[serde(default)]
+    tls_key: Option<String>,
+    // address to redirect plain HTTP requests to HTTPS on
+    #[serde(default)]
+    http_redirect: Option<String>,
     #[serde(default = "defaults::repo_directory")]
     projectroot: String,
     #[serde(default = "String::new")]
@@ -33,6 +68,47 @@ pub struct Config {
     clone_base: String,
     #[serde(default = "defaults::log_per_page")]
     log_per_page: usize,
+    #[serde(default = "defaults::scan_depth")]
+    scan_depth: usize,
+    // files larger than this many bytes are not shown in the file view
+    #[serde(default = "defaults::highlight_max_size")]
+    highlight_max_size: u64,
+    #[serde(default)]
+    projects_list: Option<String>,
+    // the URLs shown for cloning, `clone_base` is used if there are none
+    #[serde(default)]
+    clone_urls: Vec<CloneUrl>,
+    // the canonical path of projectroot, so it can be compared to the paths
+    // of repositories
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+impl Config {
+    /// The addresses to listen on, all interfaces on the configured port if
+    /// none are given.
+    fn listen_addresses(&self) -> Vec<String> {
+        if self.listen.is_empty() {
+            vec![format!("[::]:{}", self.port)]
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    fn socket_mode(&self) -> Result<u32, String> {
+        u32::from_str_radix(self.socket_mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|&mode| mode <= 0o777)
+            .ok_or_else(|| format!("socket_mode {:?} is not an octal mode", self.socket_mode))
+    }
+}
+
+/// A URL template to clone repositories from, `{repo}` is replaced with the
+/// name of the repository.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CloneUrl {
+    label: String,
+    url: String,
 }
 
 /// Defaults for the configuration options
@@ -42,6 +118,10 @@ mod defaults {
         80
     }
 
+    pub fn socket_mode() -> String {
+        "660".to_string()
+    }
+
     pub fn repo_directory() -> String {
         "repos".to_string()
     }
@@ -57,11 +137,112 @@ mod defaults {
     pub fn log_per_page() -> usize {
         100
     }
+
+    pub fn scan_depth() -> usize {
+        3
+    }
+
+    pub fn highlight_max_size() -> u64 {
+        512 * 1024
+    }
+}
+
+/// Per-repository settings, read from the `mygit` section of the git config
+/// of each repository.
+#[derive(Default)]
+struct RepoConfig {
+    // do not list the repository on the index page
+    hidden: bool,
+    // the branch to show instead of HEAD
+    default_branch: Option<String>,
+    log_per_page: Option<usize>,
+    // path of the readme file, instead of searching for README*
+    readme: Option<String>,
+    homepage: Option<String>,
+    // replace the configured clone URLs, may be given multiple times
+    clone_urls: Vec<String>,
+    // category on the index page
+    section: Option<String>,
+}
+
+impl RepoConfig {
+    fn of(repo: &Repository) -> Self {
+        let config = match repo.config() {
+            Ok(config) => config,
+            Err(_) => return Self::default(),
+        };
+        let string = |key: &str| config.get_string(key).ok().filter(|s| !s.is_empty());
+        Self {
+            hidden: config.get_bool("mygit.hidden").unwrap_or(false),
+            default_branch: string("mygit.defaultBranch"),
+            log_per_page: config
+                .get_i64("mygit.logPerPage")
+                .ok()
+                .and_then(|n| usize::try_from(n).ok())
+                .filter(|&n| n > 0),
+            readme: string("mygit.readme"),
+            homepage: string("mygit.homepage"),
+            clone_urls: config
+                .multivar("mygit.cloneUrl", None)
+                .map(|entries| {
+                    entries
+                        .into_iter()
+                        .filter_map(|entry| Some(entry.ok()?.value()?.to_string()))
+                        .filter(|url| !url.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            section: string("mygit.section"),
+        }
+    }
+
+    fn log_per_page(&self) -> usize {
+        self.log_per_page.unwrap_or(CONFIG.load().log_per_page)
+    }
+}
+
+/// The branch to show if none is given in the request. This is the first
+/// one that exists of the configured default branch, the branch HEAD points
+/// to, `main` and `master`. Otherwise the most recently changed branch is
+/// used, so repositories with a HEAD pointing to a missing branch still work.
+fn default_ref(repo: &Repository) -> String {
+    let exists = |spec: &str| {
+        repo.revparse_single(spec)
+            .and_then(|obj| obj.peel_to_commit())
+            .is_ok()
+    };
+
+    let head = repo
+        .head()
+        .ok()
+        .and_then(|head| head.shorthand().map(str::to_string));
+    RepoConfig::of(repo)
+        .default_branch
+        .into_iter()
+        .chain(head)
+        .chain(vec!["main".to_string(), "master".to_string()])
+        .find(|spec| exists(spec))
+        .or_else(|| {
+            repo.branches(Some(git2::BranchType::Local))
+                .ok()?
+                .filter_map(|branch| {
+                    let branch = branch.ok()?.0;
+                    let time = branch.get().peel_to_commit().ok()?.committer().when();
+                    Some((time, branch.name().ok()??.to_string()))
+                })
+                .max()
+                .map(|(_, name)| name)
+        })
+        // the repository is empty
+        .unwrap_or_else(|| "HEAD".to_string())
 }
 
 const HELP: &str = "\
-Usage: mygit
+Usage: mygit [check-config]
 
+SUBCOMMANDS:
+  check-config          Checks the configuration, prints the effective
+                        configuration and exits.
 FLAGS:
   -h, --help            Prints this help information and exits.
 OPTIONS:
@@ -71,16 +252,23 @@ OPTIONS:
 Mandatory or optional arguments to long options are also mandatory or optional
 for any corresponding short options.
 
+ENVIRONMENT:
+  MYGIT_<OPTION>        Overrides an option of the configuration file, e.g.
+                        MYGIT_PORT=8080 for port.
+
 Report bugs at https://todo.sr.ht/~aw/mygit
 ";
 
-static CONFIG: Lazy<Config> = Lazy::new(args);
+// The current configuration. It is replaced when the configuration file is
+// reloaded on SIGHUP, so only keep what `CONFIG.load()` returns for the
+// duration of a request.
+static CONFIG: Lazy<ArcSwap<Config>> = Lazy::new(|| ArcSwap::from_pointee(args()));
+// the configuration file given on the command line, to reload it later
+static CONFIG_FILE: OnceCell<String> = OnceCell::new();
 // so we only have to load this once to reduce startup time for syntax highlighting
 static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
 
 fn args() -> Config {
-    // TODO cli
-
     let mut pargs = pico_args::Arguments::from_env();
 
     if pargs.contains(["-h", "--help"]) {
@@ -88,31 +276,408 @@ fn args() -> Config {
         std::process::exit(0);
     }
 
+    // options can come before or after the subcommand, so they are taken
+    // first and the subcommand is what is left
     let config_filename = pargs
         .opt_value_from_str(["-c", "--config"])
-        .unwrap()
+        .unwrap_or_else(|e| usage_error(&e.to_string()))
         .unwrap_or_else(|| "mygit.toml".to_string());
+    let subcommand: Option<String> = pargs
+        .opt_free_from_str()
+        .unwrap_or_else(|e| usage_error(&e.to_string()));
+    let unused = pargs.finish();
+    if !unused.is_empty() {
+        usage_error(&format!("unexpected arguments {:?}", unused));
+    }
+
+    match subcommand.as_deref() {
+        None => {}
+        Some("check-config") => check_config(&config_filename),
+        Some(other) if other.starts_with('-') => {
+            usage_error(&format!("unknown option {:?}", other))
+        }
+        Some(other) => usage_error(&format!("unknown subcommand {:?}", other)),
+    }
+
+    let config = load_config(&config_filename).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    CONFIG_FILE.set(config_filename).unwrap();
+    config
+}
 
-    let toml_text = fs::read_to_string(&config_filename).unwrap_or_else(|_| {
+/// Exit because of invalid command line arguments.
+fn usage_error(message: &str) -> ! {
+    eprint!("{}\n\n{}", message, HELP);
+    std::process::exit(1);
+}
+
+/// Read and validate the configuration file. A missing file is not an error,
+/// the defaults are used instead. Options can be overridden with environment
+/// variables.
+fn load_config(filename: &str) -> Result<Config, String> {
+    let toml_text = fs::read_to_string(filename).unwrap_or_else(|_| {
         tide::log::warn!(
             "configuration file {:?} not found, using defaults",
-            config_filename
+            filename
         );
         String::new()
     });
-    match toml::from_str(&toml_text) {
-        Ok(config) => config,
+    let mut table: toml::value::Table = toml::from_str(&toml_text)
+        .map_err(|e| format!("could not parse configuration file: {}", e))?;
+    env_overrides(&mut table)?;
+    let mut config: Config = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| format!("invalid configuration: {}", e))?;
+
+    config.root = Path::new(&config.projectroot)
+        .canonicalize()
+        .ok()
+        .filter(|path| path.is_dir())
+        .ok_or_else(|| format!("projectroot {:?} is not a directory", config.projectroot))?;
+    if config.port == 0 {
+        return Err("port must not be 0".to_string());
+    }
+    for address in &config.listen {
+        let valid = match address.strip_prefix("unix:") {
+            Some(path) => !path.is_empty(),
+            None => address.to_socket_addrs().is_ok(),
+        };
+        if !valid {
+            return Err(format!("can not listen on {:?}", address));
+        }
+    }
+    config.socket_mode()?;
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            tls::load_certified_key(cert, key)?;
+        }
+        (None, None) => {
+            if config.http_redirect.is_some() {
+                return Err("http_redirect requires tls_cert and tls_key".to_string());
+            }
+        }
+        _ => return Err("tls_cert and tls_key must be set together".to_string()),
+    }
+    if let Some(address) = &config.http_redirect {
+        if address.to_socket_addrs().is_err() {
+            return Err(format!("can not listen on {:?}", address));
+        }
+    }
+    if config.log_per_page == 0 {
+        return Err("log_per_page must be at least 1".to_string());
+    }
+    if let Some(projects_list) = &config.projects_list {
+        if !Path::new(projects_list).is_file() {
+            return Err(format!("projects_list {:?} is not a file", projects_list));
+        }
+    }
+    Ok(config)
+}
+
+/// Override options with `MYGIT_*` environment variables, e.g. `MYGIT_PORT`
+/// for `port`. Values are parsed like in the configuration file, except for
+/// text options, which do not need quotes.
+fn env_overrides(table: &mut toml::value::Table) -> Result<(), String> {
+    // the defaults tell which type each option has
+    let defaults = toml::Value::try_from(toml::from_str::<Config>("").unwrap()).unwrap();
+
+    for (key, value) in std::env::vars() {
+        let option = match key.strip_prefix("MYGIT_") {
+            Some(option) => option.to_lowercase(),
+            None => continue,
+        };
+        let value = match defaults.get(&option) {
+            // options without a default are text as well
+            Some(toml::Value::String(_)) | None => toml::Value::String(value),
+            Some(_) => toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .ok_or_else(|| format!("invalid value {:?} in {}", value, key))?,
+        };
+        table.insert(option, value);
+    }
+    Ok(())
+}
+
+/// Implements the `check-config` subcommand: check that the configuration
+/// can be used and print the effective configuration, then exit.
+fn check_config(filename: &str) -> ! {
+    let mut ok = true;
+    match load_config(filename) {
+        Ok(config) => {
+            // the static files are read from the working directory
+            for file in &[
+                "templates/static/style.css",
+                "templates/static/robots.txt",
+                "templates/static/Feed-icon.svg",
+                "templates/static/copy.js",
+                "templates/static/lines.js",
+            ] {
+                if !Path::new(file).is_file() {
+                    eprintln!("error: {} not found", file);
+                    ok = false;
+                }
+            }
+            // only a warning, the addresses might be used by a running instance
+            for address in config.listen_addresses() {
+                let result = match address.strip_prefix("unix:") {
+                    Some(path) => match Path::new(path).parent() {
+                        Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+                            Err(format!("{:?} is not a directory", dir))
+                        }
+                        _ => Ok(()),
+                    },
+                    None => std::net::TcpListener::bind(&address)
+                        .map(drop)
+                        .map_err(|e| e.to_string()),
+                };
+                if let Err(e) = result {
+                    eprintln!("warning: can not listen on {}: {}", address, e);
+                }
+            }
+            print!("{}", toml::to_string(&config).unwrap());
+        }
         Err(e) => {
-            eprintln!("could not parse configuration file: {}", e);
-            std::process::exit(1);
+            eprintln!("error: {}", e);
+            ok = false;
         }
     }
+
+    if ok {
+        eprintln!("configuration is valid");
+        std::process::exit(0);
+    } else {
+        std::process::exit(1);
+    }
+}
+
+/// Reload the configuration file every time SIGHUP is received. If the new
+/// configuration is invalid, the old one is kept.
+fn reload_config_on_sighup() {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tide::log::error!(
+                "can not listen for SIGHUP, configuration reload disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+    for _ in signals.forever() {
+        let filename = CONFIG_FILE.get().unwrap();
+        let mut config = match load_config(filename) {
+            Ok(config) => config,
+            Err(e) => {
+                tide::log::error!("not reloading configuration: {}", e);
+                continue;
+            }
+        };
+
+        let old = CONFIG.load();
+        // the listeners are only bound once at startup
+        if config.listen_addresses() != old.listen_addresses() {
+            tide::log::warn!(
+                "listen addresses changed from {:?} to {:?}, which requires a restart to take effect",
+                old.listen_addresses(),
+                config.listen_addresses()
+            );
+        }
+        if config.socket_mode != old.socket_mode {
+            tide::log::warn!(
+                "socket_mode changed from {} to {}, which requires a restart to take effect",
+                old.socket_mode,
+                config.socket_mode
+            );
+        }
+        config.port = old.port;
+        config.listen = old.listen.clone();
+        config.socket_mode = old.socket_mode.clone();
+        // the certificate files may change, but not whether TLS is used
+        if config.tls_cert.is_some() != old.tls_cert.is_some()
+            || config.http_redirect != old.http_redirect
+        {
+            tide::log::warn!("enabling or disabling TLS requires a restart to take effect");
+        }
+        config.http_redirect = old.http_redirect.clone();
+        CONFIG.store(Arc::new(config));
+        tide::log::info!("reloaded configuration from {:?}", filename);
+    }
 }
 
 #[derive(Template)]
 #[template(path = "index.html")] // using the template in this path, relative
 struct IndexTemplate {
-    repos: Vec<Repository>,
+    // repositories grouped by category, uncategorised repositories first
+    groups: Vec<(String, Vec<Arc<metadata::RepoMetadata>>)>,
+    query: IndexQuery,
+    // commits and files matching the query, if there is one
+    results: Option<sitesearch::Results>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct IndexQuery {
+    sort: IndexSort,
+    // only show repositories with this in their name or description, and
+    // search for commits and files
+    q: String,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum IndexSort {
+    #[default]
+    Name,
+    // most recently modified first
+    Modified,
+    Owner,
+}
+
+/// A repository listed in the projects list file.
+struct ListedProject {
+    // relative to the project root
+    path: PathBuf,
+    // `None` if the repository does not exist (yet)
+    canonical: Option<PathBuf>,
+    owner: String,
+}
+
+impl ListedProject {
+    /// Whether this is the repository at the canonical path.
+    fn is(&self, canonical: &Path) -> bool {
+        match &self.canonical {
+            Some(path) => path == canonical,
+            // it may have been created since the list was read
+            None => {
+                CONFIG
+                    .load()
+                    .root
+                    .join(&self.path)
+                    .canonicalize()
+                    .ok()
+                    .as_deref()
+                    == Some(canonical)
+            }
+        }
+    }
+}
+
+/// The parsed projects list, with the file, project root and modification
+/// time it was read with.
+type ProjectsListCache = Option<(String, PathBuf, Option<SystemTime>, Arc<Vec<ListedProject>>)>;
+static PROJECTS_LIST: Lazy<Mutex<ProjectsListCache>> = Lazy::new(Default::default);
+
+/// Read the projects list file if one is configured. Like gitweb's
+/// `projects_list`, each line contains the path of a repository and
+/// optionally its owner, both URL encoded. The file is only read again when
+/// it changes.
+fn projects_list() -> Option<Arc<Vec<ListedProject>>> {
+    fn decode(s: &str) -> String {
+        percent_encoding::percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    }
+
+    let config = CONFIG.load();
+    let file = config.projects_list.clone()?;
+    let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+    let mut cache = PROJECTS_LIST.lock().unwrap();
+    if let Some((cached_file, root, cached_modified, projects)) = &*cache {
+        if *cached_file == file && *root == config.root && *cached_modified == modified {
+            return Some(projects.clone());
+        }
+    }
+
+    let text = fs::read_to_string(&file)
+        .map_err(|e| tide::log::warn!("can not read projects list {:?}: {}", file, e))
+        .unwrap_or_default();
+    let projects = Arc::new(
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.splitn(2, char::is_whitespace);
+                let path = PathBuf::from(decode(parts.next().unwrap()));
+                ListedProject {
+                    canonical: config.root.join(&path).canonicalize().ok(),
+                    path,
+                    owner: parts
+                        .next()
+                        .map(|owner| decode(owner.trim()))
+                        .unwrap_or_default(),
+                }
+            })
+            .collect::<Vec<_>>(),
+    );
+    *cache = Some((file, config.root.clone(), modified, projects.clone()));
+    Some(projects)
+}
+
+/// The owner of the repository at the path as given in the projects list
+/// file.
+fn listed_owner(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    projects_list()?
+        .iter()
+        .find(|project| project.is(&path))
+        .map(|project| project.owner.clone())
+        .filter(|owner| !owner.is_empty())
+}
+
+/// Find the paths of all exported repositories. If a projects list is
+/// configured, only the repositories listed there are used. Otherwise the
+/// project root is searched, also looking into subdirectories up to the
+/// configured depth.
+fn discover_repos() -> Vec<PathBuf> {
+    fn scan(dir: &Path, depth: usize, export_ok: &str, repos: &mut Vec<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tide::log::warn!("can not read repositories in {:?}: {}", dir, e);
+                return;
+            }
+        };
+        for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if !path.is_dir() {
+                continue;
+            }
+            // do not look for nested repositories inside of repositories
+            match metadata::git_dir(&path) {
+                // check for the export file in the git directory
+                // (the .git subfolder for non-bare repos)
+                Some(git_dir) if git_dir.join(export_ok).exists() => repos.push(path),
+                Some(_) => {}
+                None if depth > 1 => scan(&path, depth - 1, export_ok, repos),
+                None => {}
+            }
+        }
+    }
+
+    let config = CONFIG.load();
+    if let Some(projects) = projects_list() {
+        return projects
+            .iter()
+            .map(|project| match &project.canonical {
+                Some(path) => path.clone(),
+                None => config.root.join(&project.path),
+            })
+            .filter(|path| metadata::git_dir(path).is_some())
+            .collect();
+    }
+
+    let mut repos = Vec::new();
+    scan(
+        &config.root,
+        config.scan_depth,
+        &config.export_ok,
+        &mut repos,
+    );
+    repos
 }
 
 async fn index(req: Request<()>) -> tide::Result {
@@ -123,7 +688,7 @@ async fn index(req: Request<()>) -> tide::Result {
             .split(";")
             .map(|s| {
                 let mut parts = s.splitn(2, "=");
-                (parts.next().unwrap(), parts.next().unwrap())
+                (parts.next().unwrap(), parts.next().unwrap_or(""))
             })
             .collect::<std::collections::HashMap<_, _>>();
         if let Some(repo) = query.get("p") {
@@ -135,27 +700,48 @@ async fn index(req: Request<()>) -> tide::Result {
                 Some(&"shortlog") | Some(&"log") => {
                     format!("/{}/log/{}", repo, query.get("h").cloned().unwrap_or(""))
                 }
-                Some(_) => format!("/"),
+                Some(_) => "/".to_string(),
             })
             .into());
         }
     }
 
-    let repos = fs::read_dir(&CONFIG.projectroot)
-        .map(|entries| {
-            entries
-                .filter_map(|entry| Some(entry.ok()?.path()))
-                .filter_map(|entry| Repository::open(entry).ok())
-                .filter(|repo| {
-                    // check for the export file in the git directory
-                    // (the .git subfolder for non-bare repos)
-                    repo.path().join(&CONFIG.export_ok).exists()
-                })
-                .collect::<Vec<_>>()
-        })
-        .map_err(|e| tide::log::warn!("can not read repositories: {}", e))
-        .unwrap_or_default();
-    let index_template = IndexTemplate { repos };
+    let query = req.query::<IndexQuery>()?;
+    let search = query.q.to_lowercase();
+
+    let mut groups = std::collections::BTreeMap::<_, Vec<_>>::new();
+    for repo in metadata::for_repos(&discover_repos()) {
+        if repo.hidden {
+            continue;
+        }
+        if !search.is_empty()
+            && !repo.name.to_lowercase().contains(&search)
+            && !repo.description.to_lowercase().contains(&search)
+        {
+            continue;
+        }
+        groups.entry(repo.category.clone()).or_default().push(repo);
+    }
+    for repos in groups.values_mut() {
+        match query.sort {
+            IndexSort::Name => repos.sort_by(|a, b| a.name.cmp(&b.name)),
+            // empty repositories last
+            IndexSort::Modified => repos.sort_by_key(|repo| std::cmp::Reverse(repo.last_modified)),
+            IndexSort::Owner => {
+                repos.sort_by(|a, b| a.owner.cmp(&b.owner).then_with(|| a.name.cmp(&b.name)))
+            }
+        }
+    }
+    let results = if search.is_empty() {
+        None
+    } else {
+        Some(sitesearch::search(&search))
+    };
+    let index_template = IndexTemplate {
+        groups: groups.into_iter().collect(),
+        query,
+        results,
+    };
 
     Ok(index_template.into())
 }
@@ -164,33 +750,71 @@ async fn index(req: Request<()>) -> tide::Result {
 #[template(path = "repo.html")] // using the template in this path, relative
 struct RepoHomeTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     commits: Vec<Commit<'a>>,
     readme_text: String,
+    activity: Arc<activity::Activity>,
+    // the branch the README, commits and tree are shown for
+    branch: &'a str,
+    branches: Vec<String>,
 }
 
-fn repo_from_request(repo_name: &str) -> Result<Repository, tide::Error> {
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RepoHomeQuery {
+    branch: Option<String>,
+}
+
+/// The mailmap of the repository, which is loaded once per page as it is read
+/// from HEAD and the configuration. It is empty if it can not be read.
+fn load_mailmap(repo: &Repository) -> Result<Mailmap, git2::Error> {
+    repo.mailmap().or_else(|_| Mailmap::new())
+}
+
+/// The path of the repository with the given name from a URL, if it is
+/// served.
+fn repo_path(repo_name: &str) -> Result<PathBuf, tide::Error> {
     let repo_name = percent_encoding::percent_decode_str(repo_name)
         .decode_utf8_lossy()
         .into_owned();
 
-    let repo_path = Path::new(&CONFIG.projectroot)
-        .join(repo_name)
-        .canonicalize()?;
+    let config = CONFIG.load();
+    let repo_path = config.root.join(repo_name).canonicalize()?;
 
     // prevent path traversal
-    if !repo_path.starts_with(&CONFIG.projectroot) {
+    if !repo_path.starts_with(&config.root) {
         return Err(tide::Error::from_str(
             403,
             "You do not have access to this resource.",
         ));
     }
 
-    Repository::open(repo_path)
-        .ok()
+    // only serve repositories that would also be discovered for the index
+    let relative_path = repo_path.strip_prefix(&config.root).unwrap();
+    let discoverable = match projects_list() {
+        Some(projects) => projects.iter().any(|project| project.is(&repo_path)),
+        None => relative_path.components().count() <= config.scan_depth,
+    };
+    let exported = match metadata::git_dir(&repo_path) {
+        // the projects list replaces the export file check
+        Some(_) if config.projects_list.is_some() => true,
+        Some(git_dir) => git_dir.join(&config.export_ok).exists(),
+        None => false,
+    };
+    if !discoverable || !exported {
         // outside users should not be able to tell the difference between
         // nonexistent and existing but forbidden repos, so not using 403
-        .filter(|repo| repo.path().join(&CONFIG.export_ok).exists())
-        .ok_or_else(|| tide::Error::from_str(404, "This repository does not exist."))
+        return Err(tide::Error::from_str(
+            404,
+            "This repository does not exist.",
+        ));
+    }
+    Ok(repo_path)
+}
+
+fn repo_from_request(repo_name: &str) -> Result<Repository, tide::Error> {
+    Repository::open(repo_path(repo_name)?)
+        .map_err(|_| tide::Error::from_str(404, "This repository does not exist."))
 }
 
 async fn repo_home(req: Request<()>) -> tide::Result {
@@ -202,26 +826,44 @@ async fn repo_home(req: Request<()>) -> tide::Result {
         Markdown,
     }
 
-    let repo = repo_from_request(&req.param("repo_name")?)?;
-
-    let mut format = ReadmeFormat::Plaintext;
-    let readme_text = repo
-        .revparse_single("HEAD:README")
-        .or_else(|_| repo.revparse_single("HEAD:README.txt"))
-        .or_else(|_| {
-            format = ReadmeFormat::Markdown;
-            repo.revparse_single("HEAD:README.md")
-        })
-        .or_else(|_| repo.revparse_single("HEAD:README.mdown"))
-        .or_else(|_| repo.revparse_single("HEAD:README.markdown"))
-        .or_else(|_| {
-            format = ReadmeFormat::Html;
-            repo.revparse_single("HEAD:README.html")
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
+
+    let repo_config = RepoConfig::of(&repo);
+    let query = req.query::<RepoHomeQuery>()?;
+    let spec = query.branch.unwrap_or_else(|| default_ref(&repo));
+    let spec = spec.as_str();
+
+    let candidates = match repo_config.readme {
+        Some(readme) => vec![readme],
+        None => [
+            "README",
+            "README.txt",
+            "README.md",
+            "README.mdown",
+            "README.markdown",
+            "README.html",
+            "README.htm",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect(),
+    };
+    let readme_text = candidates
+        .iter()
+        .find_map(|path| {
+            let blob = repo
+                .revparse_single(&format!("{}:{}", spec, path))
+                .ok()?
+                .into_blob()
+                .ok()?;
+            let format = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+                Some("md") | Some("mdown") | Some("markdown") => ReadmeFormat::Markdown,
+                Some("html") | Some("htm") => ReadmeFormat::Html,
+                _ => ReadmeFormat::Plaintext,
+            };
+            Some((blob, format))
         })
-        .or_else(|_| repo.revparse_single("HEAD:README.htm"))
-        .ok()
-        .and_then(|readme| readme.into_blob().ok())
-        .map(|blob| {
+        .map(|(blob, format)| {
             let text = str::from_utf8(blob.content()).unwrap_or_default();
 
             // render the file contents to HTML
@@ -247,13 +889,15 @@ async fn repo_home(req: Request<()>) -> tide::Result {
         .unwrap_or_default();
 
     // get the first few commits for a preview
-    let commits = if repo.is_shallow() {
+    let commits = if repo.is_empty()? {
+        // nothing has been pushed yet
+        Vec::new()
+    } else if repo.is_shallow() {
         tide::log::warn!("repository {:?} is only a shallow clone", repo.path());
-        vec![repo.head()?.peel_to_commit().unwrap()]
+        vec![repo.revparse_single(spec)?.peel_to_commit()?]
     } else {
         let mut revwalk = repo.revwalk()?;
-        let r = req.param("ref").unwrap_or("HEAD");
-        revwalk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?;
+        revwalk.push(repo.revparse_single(spec)?.peel_to_commit()?.id())?;
 
         revwalk.set_sorting(git2::Sort::TIME).unwrap();
         revwalk
@@ -262,10 +906,22 @@ async fn repo_home(req: Request<()>) -> tide::Result {
             .collect()
     };
 
+    let activity = activity::Activity::of(&repo);
+
+    let mut branches = repo
+        .branches(Some(git2::BranchType::Local))?
+        .filter_map(|branch| Some(branch.ok()?.0.name().ok()??.to_string()))
+        .collect::<Vec<_>>();
+    branches.sort_unstable();
+
     Ok(RepoHomeTemplate {
         repo: &repo,
+        mailmap: load_mailmap(&repo)?,
         commits,
         readme_text,
+        activity,
+        branch: spec,
+        branches,
     }
     .into())
 }
@@ -274,49 +930,94 @@ async fn repo_home(req: Request<()>) -> tide::Result {
 #[template(path = "log.html")] // using the template in this path, relative
 struct RepoLogTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     commits: Vec<Commit<'a>>,
     branch: &'a str,
-    // the spec the user should be linked to to see the next page of commits
-    next_page: Option<String>,
+    // the commit after which the next page of commits continues
+    next_page: Option<git2::Oid>,
+    // the commit before which the previous page of commits ends
+    previous_page: Option<git2::Oid>,
+}
+
+/// Query parameters for paginating the log. The walk is resumed relative to
+/// the given commit, so pages stay consistent even if there are merges.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct LogPageQuery {
+    // show the commits directly after this one
+    after: Option<String>,
+    // show the commits directly before this one
+    before: Option<String>,
 }
 
 async fn repo_log(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(&req.param("repo_name")?)?;
+    use std::collections::VecDeque;
+
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     if repo.is_empty().unwrap() {
         // redirect to start page of repo
         let mut url = req.url().clone();
         url.path_segments_mut().unwrap().pop();
-        return Ok(tide::Redirect::temporary(url.to_string()).into());
+        return Ok(tide::Redirect::temporary(&url).into());
     }
 
-    let next_page_spec;
-    let mut commits = if repo.is_shallow() {
+    let per_page = RepoConfig::of(&repo).log_per_page();
+    let default = default_ref(&repo);
+    let branch = req.param("ref").unwrap_or(&default);
+
+    let query = req.query::<LogPageQuery>()?;
+    let parse_cursor = |cursor: Option<String>| -> tide::Result<Option<git2::Oid>> {
+        cursor
+            .map(|cursor| {
+                git2::Oid::from_str(&cursor)
+                    .map_err(|_| tide::Error::from_str(400, "Invalid commit id for pagination."))
+            })
+            .transpose()
+    };
+    let after = parse_cursor(query.after)?;
+    let before = parse_cursor(query.before)?;
+
+    let mut next_page = None;
+    let mut previous_page = None;
+    let commits = if repo.is_shallow() {
         tide::log::warn!("repository {:?} is only a shallow clone", repo.path());
-        next_page_spec = "".into();
-        vec![repo.head()?.peel_to_commit().unwrap()]
+        vec![repo.revparse_single(branch)?.peel_to_commit()?]
     } else {
-        let mut revwalk = repo.revwalk()?;
-        let r = req.param("ref").unwrap_or("HEAD");
-        revwalk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?;
-
-        if let Some(i) = r.rfind('~') {
-            // there is a tilde, try to find a number too
-            let n = r[i + 1..].parse::<usize>().ok().unwrap_or(1);
-            next_page_spec = format!("{}~{}", &r[..i], n + CONFIG.log_per_page);
-        } else {
-            // there was no tilde
-            next_page_spec = format!("{}~{}", r, CONFIG.log_per_page);
+        let head = repo.revparse_single(branch)?.peel_to_commit()?.id();
+        // otherwise the whole history would be walked without finding it
+        for &cursor in after.iter().chain(&before) {
+            if cursor != head && !repo.graph_descendant_of(head, cursor).unwrap_or(false) {
+                return Err(tide::Error::from_str(
+                    404,
+                    "The commit for pagination is not on this branch.",
+                ));
+            }
         }
 
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(head)?;
+
         revwalk.set_sorting(git2::Sort::TIME).unwrap();
-        let commits = revwalk.filter_map(|oid| repo.find_commit(oid.unwrap()).ok()); // TODO error handling
+        let mut commits = revwalk.filter_map(|oid| repo.find_commit(oid.unwrap()).ok()); // TODO error handling
+
+        // move to the cursor before filtering, so the walk stops there even
+        // if the cursor itself would be filtered out
+        let commits: Box<dyn Iterator<Item = Commit>> = match (before, after) {
+            (Some(before), _) => Box::new(commits.take_while(move |commit| commit.id() != before)),
+            (None, Some(after)) => {
+                // skip everything up to and including the cursor
+                commits.find(|commit| commit.id() == after);
+                Box::new(commits)
+            }
+            (None, None) => Box::new(commits),
+        };
 
         // filter for specific file if necessary
-        if let Ok(path) = req.param("object_name") {
+        let commits: Box<dyn Iterator<Item = Commit>> = if let Ok(path) = req.param("object_name") {
             let mut options = DiffOptions::new();
             options.pathspec(path);
-            commits
-                .filter(|commit|
+            let repo = &repo;
+            Box::new(commits.filter(move |commit|
                 // check that the given file was affected from any of the parents
                 commit.parents().any(|parent|
                     repo.diff_tree_to_tree(
@@ -324,34 +1025,50 @@ async fn repo_log(req: Request<()>) -> tide::Result {
                         Some(&parent.tree().unwrap()),
                         Some(&mut options),
                     ).unwrap().stats().unwrap().files_changed()>0
-                ))
-                .take(CONFIG.log_per_page + 1)
-                .collect()
+                )))
+        } else {
+            Box::new(commits)
+        };
+
+        if before.is_some() {
+            // keep the last page worth of commits before the cursor, plus one
+            // more to check if there even is a previous page
+            let mut page = VecDeque::with_capacity(per_page + 1);
+            for commit in commits {
+                if page.len() > per_page {
+                    page.pop_front();
+                }
+                page.push_back(commit);
+            }
+            if page.len() > per_page {
+                // remove additional commit from previous page check
+                page.pop_front();
+                previous_page = page.front().map(Commit::id);
+            }
+            next_page = page.back().map(Commit::id);
+            page.into()
         } else {
-            commits.take(CONFIG.log_per_page + 1).collect()
+            let mut page = commits.take(per_page + 1).collect::<Vec<_>>();
+            // check if there even is a next page
+            if page.len() > per_page {
+                // remove additional commit from next page check
+                page.pop();
+                next_page = page.last().map(Commit::id);
+            }
+            if after.is_some() {
+                previous_page = page.first().map(Commit::id);
+            }
+            page
         }
     };
 
-    // check if there even is a next page
-    let next_page = if commits.len() < CONFIG.log_per_page + 1 {
-        None
-    } else {
-        // remove additional commit from next page check
-        commits.pop();
-        Some(next_page_spec)
-    };
-
-    let head_branch = repo.head()?;
-    let branch = req
-        .param("ref")
-        .ok()
-        .or_else(|| head_branch.shorthand())
-        .unwrap();
     let tmpl = RepoLogTemplate {
         repo: &repo,
+        mailmap: load_mailmap(&repo)?,
         commits,
         branch,
         next_page,
+        previous_page,
     };
     Ok(tmpl.into())
 }
@@ -360,17 +1077,18 @@ async fn repo_log(req: Request<()>) -> tide::Result {
 #[template(path = "refs.html")] // using the template in this path, relative
 struct RepoRefTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     branches: Vec<Reference<'a>>,
     tags: Vec<(String, String, Signature<'static>)>,
 }
 
 async fn repo_refs(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(&req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     if repo.is_empty().unwrap() {
         // redirect to start page of repo
         let mut url = req.url().clone();
         url.path_segments_mut().unwrap().pop();
-        return Ok(tide::Redirect::temporary(url.to_string()).into());
+        return Ok(tide::Redirect::temporary(&url).into());
     }
 
     let branches = repo
@@ -411,79 +1129,130 @@ async fn repo_refs(req: Request<()>) -> tide::Result {
     tags.sort_unstable_by(|(_, _, a), (_, _, b)| a.when().cmp(&b.when()).reverse());
     let tmpl = RepoRefTemplate {
         repo: &repo,
+        mailmap: load_mailmap(&repo)?,
         branches,
         tags,
     };
     Ok(tmpl.into())
 }
 
-fn last_commit_for<'a, S: git2::IntoCString>(
+#[derive(Template)]
+#[template(path = "contributors.html")] // using the template in this path, relative
+struct RepoContributorsTemplate<'a> {
     repo: &'a Repository,
-    spec: &str,
-    path: S,
-) -> Commit<'a> {
-    let mut revwalk = repo.revwalk().unwrap();
-    revwalk
-        .push(
-            repo
-                // we already know this has to be a commit-ish
-                .revparse_single(spec)
-                .unwrap()
-                .peel_to_commit()
-                .unwrap()
-                .id(),
-        )
-        .unwrap();
-    revwalk.set_sorting(git2::Sort::TIME).unwrap();
+    contributors: &'a [contributors::Contributor],
+    spec: &'a str,
+    since: &'a str,
+    until: &'a str,
+}
 
-    let mut options = DiffOptions::new();
-    options.pathspec(path);
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ContributorsQuery {
+    #[serde(rename = "ref")]
+    spec: String,
+    // dates in the format YYYY-MM-DD, both inclusive
+    since: String,
+    until: String,
+}
 
-    revwalk
-        .filter_map(|oid| repo.find_commit(oid.unwrap()).ok()) // TODO error handling
-        .filter(|commit| {
-            let tree = commit.tree().unwrap();
-            if commit.parent_count() == 0 {
-                repo.diff_tree_to_tree(None, Some(&tree), Some(&mut options))
-                    .unwrap()
-                    .stats()
-                    .unwrap()
-                    .files_changed()
-                    > 0
-            } else {
-                // check that the given file was affected from any of the parents
-                commit.parents().any(|parent| {
-                    repo.diff_tree_to_tree(
-                        parent.tree().ok().as_ref(),
-                        Some(&tree),
-                        Some(&mut options),
-                    )
-                    .unwrap()
-                    .stats()
-                    .unwrap()
-                    .files_changed()
-                        > 0
-                })
-            }
-        })
-        .next()
-        .expect("file was not part of any commit")
+async fn repo_contributors(req: Request<()>) -> tide::Result {
+    use chrono::NaiveDate;
+
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
+    if repo.is_empty().unwrap() {
+        // redirect to start page of repo
+        let mut url = req.url().clone();
+        url.path_segments_mut().unwrap().pop();
+        return Ok(tide::Redirect::temporary(&url).into());
+    }
+
+    let query = req.query::<ContributorsQuery>()?;
+    let default = default_ref(&repo);
+    let spec = if query.spec.is_empty() {
+        &default
+    } else {
+        &query.spec
+    };
+    let parse_date = |date: &str| -> tide::Result<Option<NaiveDate>> {
+        if date.is_empty() {
+            Ok(None)
+        } else {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| tide::Error::from_str(400, "Dates must be given as YYYY-MM-DD."))
+        }
+    };
+    let since = parse_date(&query.since)?.map(|date| date.and_hms(0, 0, 0).timestamp());
+    let until = parse_date(&query.until)?.map(|date| date.and_hms(23, 59, 59).timestamp());
+
+    // walking the history can take a while, so it should not block the
+    // other requests
+    let head = repo.revparse_single(spec)?.peel_to_commit()?.id();
+    let repo_path = repo.path().to_path_buf();
+    let contributors = async_std::task::spawn_blocking(move || {
+        contributors::of(&Repository::open(repo_path)?, head, since, until)
+    })
+    .await?;
+
+    let tmpl = RepoContributorsTemplate {
+        repo: &repo,
+        contributors: &contributors,
+        spec,
+        since: &query.since,
+        until: &query.until,
+    };
+    Ok(tmpl.into())
 }
 
 #[derive(Template)]
 #[template(path = "tree.html")] // using the template in this path, relative
 struct RepoTreeTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     tree: Tree<'a>,
     path: &'a Path,
     spec: &'a str,
     last_commit: Commit<'a>,
+    // the commits that last changed each entry, by name
+    entry_commits: Arc<HashMap<Vec<u8>, git2::Oid>>,
+    submodules: Vec<submodule::Submodule>,
+    // the tree of the whole commit, to resolve symbolic links in
+    root: Tree<'a>,
+}
+
+impl RepoTreeTemplate<'_> {
+    fn entry_commit(&self, entry: &git2::TreeEntry) -> Option<Commit<'_>> {
+        let oid = self.entry_commits.get(entry.name_bytes())?;
+        self.repo.find_commit(*oid).ok()
+    }
+
+    fn submodule(&self, entry: &git2::TreeEntry) -> Option<submodule::SubmoduleEntry> {
+        if entry.filemode() != submodule::FILEMODE {
+            return None;
+        }
+        let path = self.path.join(entry.name()?);
+        Some(submodule::entry(
+            self.repo,
+            &self.submodules,
+            &path,
+            entry.id(),
+        ))
+    }
+
+    fn symlink(&self, entry: &git2::TreeEntry) -> Option<symlink::Symlink> {
+        if entry.filemode() != symlink::FILEMODE {
+            return None;
+        }
+        symlink::entry(self.repo, &self.root, &self.path.join(entry.name()?))
+    }
 }
 
 #[derive(Template)]
 #[template(path = "commit.html")] // using the template in this path, relative
 struct RepoCommitTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     commit: Commit<'a>,
     diff: &'a Diff<'a>,
 }
@@ -523,7 +1292,7 @@ impl RepoCommitTemplate<'_> {
             .find_syntax_by_name("Diff")
             .expect("diff syntax missing");
         let mut highlighter =
-            ClassedHTMLGenerator::new_with_class_style(&syntax, &SYNTAXES, ClassStyle::Spaced);
+            ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, ClassStyle::Spaced);
         LinesWithEndings::from(&buf)
             .for_each(|line| highlighter.parse_html_for_line_which_includes_newline(line));
         highlighter.finalize()
@@ -536,7 +1305,7 @@ impl RepoCommitTemplate<'_> {
 
         // add badge if this commit is a tag
         let descr = self.commit.as_object().describe(
-            &DescribeOptions::new()
+            DescribeOptions::new()
                 .describe_tags()
                 .max_candidates_tags(0),
         );
@@ -572,7 +1341,7 @@ impl RepoCommitTemplate<'_> {
 }
 
 async fn repo_commit(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     let commit = repo
         .revparse_single(req.param("commit")?)?
         .peel_to_commit()?;
@@ -589,6 +1358,7 @@ async fn repo_commit(req: Request<()>) -> tide::Result {
 
     let tmpl = RepoCommitTemplate {
         repo: &repo,
+        mailmap: load_mailmap(&repo)?,
         commit,
         diff: &diff,
     };
@@ -599,75 +1369,241 @@ async fn repo_commit(req: Request<()>) -> tide::Result {
 #[template(path = "tag.html")]
 struct RepoTagTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     tag: Tag<'a>,
 }
 
 async fn repo_tag(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     let tag = repo.revparse_single(req.param("tag")?)?.peel_to_tag();
 
     if let Ok(tag) = tag {
-        let tmpl = RepoTagTemplate { repo: &repo, tag };
+        let tmpl = RepoTagTemplate {
+            repo: &repo,
+            mailmap: load_mailmap(&repo)?,
+            tag,
+        };
         Ok(tmpl.into())
     } else {
         Ok(tide::Redirect::permanent(format!(
             "/{}/commit/{}",
-            req.param("repo_name")?,
+            dispatch::repo_name(&req)?,
             req.param("tag")?
         ))
         .into())
     }
 }
 
+#[derive(Template)]
+#[template(path = "search.html")] // using the template in this path, relative
+struct RepoSearchTemplate<'a> {
+    repo: &'a Repository,
+    query: SearchQuery,
+    // the ref that was searched
+    spec: &'a str,
+    results: Option<search::Results>,
+    // why the query could not be searched
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SearchQuery {
+    q: String,
+    #[serde(rename = "ref")]
+    spec: Option<String>,
+    // literal, regex or path
+    mode: Option<String>,
+    // only search files matching this glob pattern
+    path: String,
+    ignore_case: Option<String>,
+}
+
+impl SearchQuery {
+    fn mode(&self) -> &str {
+        self.mode.as_deref().unwrap_or("literal")
+    }
+}
+
+async fn repo_search(req: Request<()>) -> tide::Result {
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
+    let query = req.query::<SearchQuery>()?;
+
+    let spec = query.spec.clone().unwrap_or_else(|| default_ref(&repo));
+    let mode = search::Mode::parse(query.mode())
+        .ok_or_else(|| tide::Error::from_str(400, "The mode has to be literal, regex or path."))?;
+
+    let (results, error) = if query.q.is_empty() || repo.is_empty()? {
+        (None, None)
+    } else {
+        let tree = repo.revparse_single(&spec)?.peel_to_tree()?.id();
+        let path = match query.path.as_str() {
+            "" => Ok(None),
+            path => glob::Pattern::new(path)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+        };
+        // building the index reads every file, so it should not block the
+        // other requests
+        let repo_path = repo.path().to_path_buf();
+        let q = query.q.clone();
+        let ignore_case = query.ignore_case.is_some();
+        let results = async_std::task::spawn_blocking(move || -> Result<_, git2::Error> {
+            let repo = Repository::open(repo_path)?;
+            let tree = repo.find_tree(tree)?;
+            Ok(path.and_then(|path| {
+                search::search(&repo, &tree, &q, mode, ignore_case, path.as_ref())
+            }))
+        })
+        .await?;
+        match results {
+            Ok(results) => (Some(results), None),
+            Err(e) => (None, Some(e)),
+        }
+    };
+
+    Ok(RepoSearchTemplate {
+        repo: &repo,
+        spec: &spec,
+        results,
+        error,
+        query,
+    }
+    .into())
+}
+
 #[derive(Template)]
 #[template(path = "file.html")] // using the template in this path, relative
 struct RepoFileTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     path: &'a Path,
     file_text: &'a str,
     spec: &'a str,
     last_commit: Commit<'a>,
+    symlink: Option<symlink::Symlink>,
+    lfs: Option<lfs::Pointer>,
+    // whether the LFS object is available on this server
+    lfs_available: bool,
+    // the lines that are shown, if not all of them
+    lines: Option<LineRange>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FileQuery {
+    // only show these lines, e.g. 10-25
+    lines: Option<String>,
+}
+
+/// An inclusive range of line numbers, starting at 1.
+struct LineRange {
+    first: usize,
+    last: usize,
+}
+
+impl LineRange {
+    /// Parse a range like `10-25`, or a single line like `10`.
+    fn parse(text: &str) -> Option<Self> {
+        let (first, last) = text.split_once('-').unwrap_or((text, text));
+        let first = first.trim().trim_start_matches('L').parse().ok()?;
+        let last = last.trim().trim_start_matches('L').parse().ok()?;
+        Some(Self { first, last }).filter(|range| range.first >= 1 && range.first <= range.last)
+    }
+}
+
+/// Keep track of the `<span>`s that are open after a line of highlighted
+/// HTML, because syntect leaves them open across lines.
+fn track_spans(open: &mut Vec<String>, line: &str) {
+    let mut rest = line;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let tag = &rest[..end];
+        if tag.starts_with("</span") {
+            open.pop();
+        } else if tag.starts_with("<span") {
+            open.push(tag.to_string());
+        }
+        rest = &rest[end..];
+    }
 }
 
 async fn repo_file(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
+    let query = req.query::<FileQuery>()?;
+    let lines =
+        match &query.lines {
+            Some(lines) => Some(LineRange::parse(lines).ok_or_else(|| {
+                tide::Error::from_str(400, "The lines have to be given like 10-25.")
+            })?),
+            None => None,
+        };
 
     if repo.is_empty().unwrap() {
         // redirect to start page of repo
         let mut url = req.url().clone();
         url.path_segments_mut().unwrap().pop();
-        return Ok(tide::Redirect::temporary(url.to_string()).into());
+        return Ok(tide::Redirect::temporary(&url).into());
     }
 
-    let head = repo.head()?;
-    let spec = req.param("ref").ok().or_else(|| head.shorthand()).unwrap();
+    let default = default_ref(&repo);
+    let spec = req.param("ref").unwrap_or(&default);
     let commit = repo.revparse_single(spec)?.peel_to_commit()?;
     let tree = commit.tree()?;
 
     let (path, tree_obj) = if let Ok(path) = req.param("object_name") {
         let path = Path::new(path);
-        (path, tree.get_path(&path)?.to_object(&repo)?)
+        let entry = tree.get_path(path)?;
+        if entry.filemode() == submodule::FILEMODE {
+            // the commit is in another repository
+            let submodules = submodule::gitmodules(&repo, &tree);
+            return match submodule::entry(&repo, &submodules, path, entry.id()).link {
+                Some(link) => Ok(tide::Redirect::temporary(link).into()),
+                None => Err(tide::Error::from_str(
+                    404,
+                    "This is a submodule, its repository is not available here.",
+                )),
+            };
+        }
+        // large files are not shown, so they are not read either
+        let (size, kind) = repo.odb()?.read_header(entry.id())?;
+        if kind == git2::ObjectType::Blob && size as u64 > CONFIG.load().highlight_max_size {
+            (path, None)
+        } else {
+            (path, Some(entry.to_object(&repo)?))
+        }
     } else {
-        (Path::new(""), tree.into_object())
+        (Path::new(""), Some(tree.into_object()))
     };
 
-    let last_commit = last_commit_for(&repo, spec, path);
+    let last_commit =
+        history::last_commit_for(&repo, &commit, path).unwrap_or_else(|| commit.clone());
 
     // TODO make sure I am escaping html properly here
     // TODO allow disabling of syntax highlighting
     // TODO -- dont pull in memory, use iterators if possible
-    let tmpl = match tree_obj.into_tree() {
+    let tmpl = match tree_obj.map(git2::Object::into_tree) {
         // this is a subtree
-        Ok(tree) => RepoTreeTemplate {
+        Some(Ok(tree)) => RepoTreeTemplate {
             repo: &repo,
+            mailmap: load_mailmap(&repo)?,
             tree,
             path,
-            spec: &spec,
+            spec,
             last_commit,
+            entry_commits: history::last_commits_for_entries(&repo, &commit, path),
+            submodules: submodule::gitmodules(&repo, &commit.tree()?),
+            root: commit.tree()?,
         }
         .into(),
-        // this is not a subtree, so it should be a blob i.e. file
-        Err(tree_obj) => {
+        // this is not a subtree, so it should be a blob i.e. file, which
+        // is not read if it is too large
+        tree_obj => {
+            let blob = match tree_obj {
+                Some(Err(tree_obj)) => tree_obj.into_blob().ok(),
+                _ => None,
+            };
             let extension = path
                 .extension()
                 .and_then(std::ffi::OsStr::to_str)
@@ -676,29 +1612,50 @@ async fn repo_file(req: Request<()>) -> tide::Result {
                 .find_syntax_by_extension(extension)
                 .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
 
-            let blob = tree_obj.as_blob().unwrap();
-            let output = if blob.is_binary() {
+            let max_size = CONFIG.load().highlight_max_size;
+            let pointer = blob
+                .as_ref()
+                .and_then(|blob| lfs::Pointer::parse(blob.content()));
+            let lfs_object = pointer.as_ref().and_then(|pointer| pointer.object(&repo));
+            // large LFS objects are not read either
+            let lfs_content = match (&pointer, &lfs_object) {
+                (Some(pointer), Some(path)) if pointer.size <= max_size => Some(fs::read(path)?),
+                _ => None,
+            };
+            // the contents to show, if they are not too large
+            let content = match (&lfs_object, &blob) {
+                (Some(_), _) => lfs_content.as_deref(),
+                (None, Some(blob)) => Some(blob.content()),
+                (None, None) => None,
+            };
+            let is_binary = match (&lfs_content, &blob) {
+                // the same check git uses
+                (Some(content), _) => content.iter().take(8000).any(|&b| b == 0),
+                (None, Some(blob)) => blob.is_binary(),
+                (None, None) => false,
+            };
+
+            let output = if content.is_none() || is_binary {
                 // this is not a text file, but try to serve the file if the MIME type
                 // can give a hint at how
-                let mime = http::Mime::from_extension(extension).unwrap_or_else(|| {
-                    if blob.is_binary() {
-                        http::mime::BYTE_STREAM
-                    } else {
-                        http::mime::PLAIN
-                    }
-                });
+                let mime = http::Mime::from_extension(extension).unwrap_or(http::mime::BYTE_STREAM);
                 match mime.basetype() {
-                    "text" => unreachable!("git detected this file as binary"),
                     "image" => format!(
                         "<img src=\"/{}/tree/{}/raw/{}\" />",
-                        req.param("repo_name").unwrap(),
+                        dispatch::repo_name(&req).unwrap(),
                         spec,
                         path.display()
                     ),
                     tag@"audio"|tag@"video" => format!(
                         "<{} src=\"/{}/tree/{}/raw/{}\" controls>Your browser does not have support for playing this {0} file.</{0}>",
                         tag,
-                        req.param("repo_name").unwrap(),
+                        dispatch::repo_name(&req).unwrap(),
+                        spec,
+                        path.display()
+                    ),
+                    _ if content.is_none() => format!(
+                        "This file is too large to display, <a href=\"/{}/tree/{}/raw/{}\">view raw</a>.",
+                        dispatch::repo_name(&req).unwrap(),
                         spec,
                         path.display()
                     ),
@@ -706,10 +1663,10 @@ async fn repo_file(req: Request<()>) -> tide::Result {
                 }
             } else {
                 // get file contents from git object
-                let file_string = str::from_utf8(tree_obj.as_blob().unwrap().content())?;
+                let file_string = str::from_utf8(content.unwrap_or_default())?;
                 // create a highlighter that uses CSS classes so we can use prefers-color-scheme
                 let mut highlighter = ClassedHTMLGenerator::new_with_class_style(
-                    &syntax,
+                    syntax,
                     &SYNTAXES,
                     ClassStyle::Spaced,
                 );
@@ -719,29 +1676,58 @@ async fn repo_file(req: Request<()>) -> tide::Result {
                 // use oid so it is a permalink
                 let prefix = format!(
                     "/{}/tree/{}/item/{}",
-                    req.param("repo_name").unwrap(),
+                    dispatch::repo_name(&req).unwrap(),
                     commit.id(),
                     path.display()
                 );
 
-                let mut output = String::from("<pre>\n");
-                for (n, line) in highlighter.finalize().lines().enumerate() {
+                let html = highlighter.finalize();
+                let line_count = html.lines().count();
+                let (first, last) = match &lines {
+                    Some(lines) if lines.first > line_count => {
+                        return Err(tide::Error::from_str(
+                            404,
+                            format!("This file only has {} lines.", line_count),
+                        ))
+                    }
+                    Some(lines) => (lines.first, lines.last.min(line_count)),
+                    None => (1, line_count),
+                };
+
+                let mut output = String::from("<pre class='file'>\n");
+                // the spans that are open at the start of the shown lines
+                let mut open: Vec<String> = Vec::new();
+                for (n, line) in html.lines().enumerate() {
+                    let n = n + 1;
+                    if n == first {
+                        output.push_str(&open.concat());
+                    }
+                    track_spans(&mut open, line);
+                    if n < first || n > last {
+                        continue;
+                    }
                     output.push_str(&format!(
                         "<a href='{1}#L{0}' id='L{0}' class='line'>{0}</a>{2}\n",
-                        n + 1,
-                        prefix,
-                        line,
+                        n, prefix, line,
                     ));
+                    if n == last {
+                        output.push_str(&"</span>".repeat(open.len()));
+                    }
                 }
                 output.push_str("</pre>\n");
                 output
             };
             RepoFileTemplate {
                 repo: &repo,
+                mailmap: load_mailmap(&repo)?,
                 path,
                 file_text: &output,
-                spec: &spec,
+                spec,
                 last_commit,
+                symlink: symlink::entry(&repo, &commit.tree()?, path),
+                lfs_available: lfs_object.is_some(),
+                lfs: pointer,
+                lines,
             }
             .into()
         }
@@ -749,35 +1735,32 @@ async fn repo_file(req: Request<()>) -> tide::Result {
     Ok(tmpl)
 }
 
-async fn repo_file_raw(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(req.param("repo_name")?)?;
+/// What to serve for a raw file: the blob, or the LFS object it points to if
+/// that is available.
+fn raw_source(req: &Request<()>) -> tide::Result<raw::Source> {
+    let repo = repo_from_request(dispatch::repo_name(req)?)?;
 
     let spec = req.param("ref").unwrap();
     let tree = repo.revparse_single(spec)?.peel_to_commit()?.tree()?;
 
     let path = Path::new(req.param("object_name")?);
-    let blob = tree
-        .get_path(path)
-        .and_then(|tree_entry| tree_entry.to_object(&repo)?.peel_to_blob());
-    match blob {
-        Ok(blob) => {
-            let extension = path
-                .extension()
-                .and_then(std::ffi::OsStr::to_str)
-                .unwrap_or_default();
-            let mime = http::Mime::from_extension(extension).unwrap_or_else(|| {
-                if blob.is_binary() {
-                    http::mime::BYTE_STREAM
-                } else {
-                    http::mime::PLAIN
+    let entry = tree.get_path(path).and_then(|entry| match entry.kind() {
+        Some(git2::ObjectType::Blob) => Ok(entry),
+        _ => Err(git2::Error::from_str("not a file")),
+    });
+    match entry {
+        Ok(entry) => {
+            // only small blobs can be LFS pointers, others are not read here
+            let (size, _) = repo.odb()?.read_header(entry.id())?;
+            if size <= lfs::MAX_POINTER_SIZE {
+                let blob = repo.find_blob(entry.id())?;
+                if let Some(object) =
+                    lfs::Pointer::parse(blob.content()).and_then(|pointer| pointer.object(&repo))
+                {
+                    return Ok(raw::Source::File(object));
                 }
-            });
-
-            // have to put the blob's content into a Vec here because the repo will be dropped
-            Ok(Response::builder(200)
-                .body(blob.content().to_vec())
-                .content_type(mime)
-                .build())
+            }
+            Ok(raw::Source::Blob(repo.path().to_path_buf(), entry.id()))
         }
         Err(e) => Err(tide::Error::from_str(
             404,
@@ -789,12 +1772,21 @@ async fn repo_file_raw(req: Request<()>) -> tide::Result {
     }
 }
 
+async fn repo_file_raw(req: Request<()>) -> tide::Result {
+    let source = raw_source(&req)?;
+    let extension = Path::new(req.param("object_name")?)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or_default();
+    raw::respond(&req, source, http::Mime::from_extension(extension)).await
+}
+
 async fn git_data(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     let path = req
         .url()
         .path()
-        .strip_prefix(&format!("/{}/", req.param("repo_name").unwrap()))
+        .strip_prefix(&format!("/{}/", dispatch::repo_name(&req).unwrap()))
         .unwrap_or_default();
     let path = repo.path().join(path).canonicalize()?;
 
@@ -837,6 +1829,14 @@ async fn static_resource(req: Request<()>) -> tide::Result {
             File::open("templates/static/Feed-icon.svg").unwrap(),
             http::mime::SVG,
         )),
+        "/copy.js" => Some((
+            File::open("templates/static/copy.js").unwrap(),
+            http::mime::JAVASCRIPT,
+        )),
+        "/lines.js" => Some((
+            File::open("templates/static/lines.js").unwrap(),
+            http::mime::JAVASCRIPT,
+        )),
         _ => None,
     };
 
@@ -910,13 +1910,14 @@ async fn static_resource(req: Request<()>) -> tide::Result {
 #[template(path = "log.xml")]
 struct RepoLogFeedTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     commits: Vec<Commit<'a>>,
     branch: &'a str,
     base_url: &'a str,
 }
 
 async fn repo_log_feed(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(&req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     if repo.is_empty().unwrap() {
         // show a server error
         return Err(tide::Error::from_str(
@@ -925,28 +1926,23 @@ async fn repo_log_feed(req: Request<()>) -> tide::Result {
         ));
     }
 
+    let default = default_ref(&repo);
+    let branch = req.param("ref").unwrap_or(&default);
+
     let commits = if repo.is_shallow() {
         tide::log::warn!("repository {:?} is only a shallow clone", repo.path());
-        vec![repo.head()?.peel_to_commit().unwrap()]
+        vec![repo.revparse_single(branch)?.peel_to_commit()?]
     } else {
         let mut revwalk = repo.revwalk()?;
-        let r = req.param("ref").unwrap_or("HEAD");
-        revwalk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?;
+        revwalk.push(repo.revparse_single(branch)?.peel_to_commit()?.id())?;
 
         revwalk.set_sorting(git2::Sort::TIME).unwrap();
         revwalk
             .filter_map(|oid| repo.find_commit(oid.unwrap()).ok()) // TODO error handling
-            .take(CONFIG.log_per_page)
+            .take(RepoConfig::of(&repo).log_per_page())
             .collect()
     };
 
-    let head_branch = repo.head()?;
-    let branch = req
-        .param("ref")
-        .ok()
-        .or_else(|| head_branch.shorthand())
-        .unwrap();
-
     let mut url = req.url().clone();
     {
         let mut segments = url.path_segments_mut().unwrap();
@@ -959,6 +1955,7 @@ async fn repo_log_feed(req: Request<()>) -> tide::Result {
 
     let tmpl = RepoLogFeedTemplate {
         repo: &repo,
+        mailmap: load_mailmap(&repo)?,
         commits,
         branch,
         base_url: url.as_str(),
@@ -972,12 +1969,13 @@ async fn repo_log_feed(req: Request<()>) -> tide::Result {
 #[template(path = "refs.xml")]
 struct RepoRefFeedTemplate<'a> {
     repo: &'a Repository,
+    mailmap: Mailmap,
     tags: Vec<(String, String, Signature<'static>, String)>,
     base_url: &'a str,
 }
 
 async fn repo_refs_feed(req: Request<()>) -> tide::Result {
-    let repo = repo_from_request(&req.param("repo_name")?)?;
+    let repo = repo_from_request(dispatch::repo_name(&req)?)?;
     if repo.is_empty().unwrap() {
         // show a server error
         return Err(tide::Error::from_str(
@@ -1033,6 +2031,7 @@ async fn repo_refs_feed(req: Request<()>) -> tide::Result {
 
     let tmpl = RepoRefFeedTemplate {
         repo: &repo,
+        mailmap: load_mailmap(&repo)?,
         tags,
         base_url: url.as_str(),
     };
@@ -1042,6 +2041,11 @@ async fn repo_refs_feed(req: Request<()>) -> tide::Result {
 #[async_std::main]
 async fn main() -> Result<(), std::io::Error> {
     tide::log::start();
+    // read the configuration now, so errors are reported before listening
+    let config = CONFIG.load_full();
+    std::thread::spawn(reload_config_on_sighup);
+    std::thread::spawn(sitesearch::update_forever);
+
     let mut app = tide::new();
     app.with(errorpage::ErrorToErrorpage);
     app.at("/").get(index);
@@ -1049,38 +2053,129 @@ async fn main() -> Result<(), std::io::Error> {
     app.at("/style.css").get(static_resource);
     app.at("/robots.txt").get(static_resource);
     app.at("/Feed-icon.svg").get(static_resource);
+    app.at("/copy.js").get(static_resource);
+    app.at("/lines.js").get(static_resource);
 
-    app.at("/:repo_name").get(repo_home);
-    app.at("/:repo_name/").get(repo_home);
+    // the routes of the pages of a repository, relative to its name
+    let mut pages = tide::new();
+    pages.at("/").get(repo_home);
 
     // git clone stuff
-    app.at("/:repo_name/info/refs").get(git_data);
-    app.at("/:repo_name/HEAD").get(git_data);
-    app.at("/:repo_name/objects/*obj").get(git_data);
+    pages.at("/info/lfs/objects/batch").post(lfs::batch);
+    pages.at("/info/lfs/objects/:oid").get(lfs::download);
+    pages.at("/info/refs").get(git_data);
+    pages.at("/HEAD").get(git_data);
+    pages.at("/objects/*obj").get(git_data);
 
     // web pages
-    app.at("/:repo_name/commit/:commit").get(repo_commit);
-    app.at("/:repo_name/refs").get(repo_refs);
-    app.at("/:repo_name/refs/").get(repo_refs);
-    app.at("/:repo_name/refs.xml").get(repo_refs_feed);
-    app.at("/:repo_name/refs/:tag").get(repo_tag);
-    app.at("/:repo_name/log").get(repo_log);
-    app.at("/:repo_name/log/").get(repo_log);
-    app.at("/:repo_name/log/:ref").get(repo_log); // ref is optional
-    app.at("/:repo_name/log/:ref/").get(repo_log); // ref is optional
-    app.at("/:repo_name/log/:ref/*object_name").get(repo_log);
-    app.at("/:repo_name/log.xml").get(repo_log_feed);
-    app.at("/:repo_name/log/:ref/feed.xml").get(repo_log_feed); // ref is optional
-    app.at("/:repo_name/tree").get(repo_file);
-    app.at("/:repo_name/tree/").get(repo_file);
-    app.at("/:repo_name/tree/:ref").get(repo_file); // ref is optional
-    app.at("/:repo_name/tree/:ref/").get(repo_file); // ref is optional
-    app.at("/:repo_name/tree/:ref/item/*object_name")
-        .get(repo_file);
-    app.at("/:repo_name/tree/:ref/raw/*object_name")
-        .get(repo_file_raw);
-
-    app.at("*").all(static_resource);
-    app.listen(format!("[::]:{}", CONFIG.port)).await?;
+    pages.at("/commit/:commit").get(repo_commit);
+    pages.at("/refs").get(repo_refs);
+    pages.at("/refs/").get(repo_refs);
+    pages.at("/refs.xml").get(repo_refs_feed);
+    pages.at("/refs/:tag").get(repo_tag);
+    pages.at("/log").get(repo_log);
+    pages.at("/log/").get(repo_log);
+    pages.at("/log/:ref").get(repo_log); // ref is optional
+    pages.at("/log/:ref/").get(repo_log); // ref is optional
+    pages.at("/log/:ref/*object_name").get(repo_log);
+    pages.at("/contributors").get(repo_contributors);
+    pages.at("/search").get(repo_search);
+    pages.at("/log.xml").get(repo_log_feed);
+    pages.at("/log/:ref/feed.xml").get(repo_log_feed); // ref is optional
+    pages.at("/tree").get(repo_file);
+    pages.at("/tree/").get(repo_file);
+    pages.at("/tree/:ref").get(repo_file); // ref is optional
+    pages.at("/tree/:ref/").get(repo_file); // ref is optional
+    pages.at("/tree/:ref/item/*object_name").get(repo_file);
+    pages.at("/tree/:ref/raw/*object_name").get(repo_file_raw);
+
+    pages.at("*").all(static_resource);
+    app.at("*").all(dispatch::Dispatch::new(pages, |name| {
+        repo_path(name).is_ok()
+    }));
+    // the certificate was already checked when loading the configuration
+    let tls = config.tls_cert.as_ref().map(|_| tls::acceptor().unwrap());
+    let mut listener = tide::listener::ConcurrentListener::new();
+    for address in config.listen_addresses() {
+        bind(&address, &config, tls.as_ref(), &mut listener).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("can not listen on {}: {}", address, e))
+        })?;
+    }
+
+    if let Some(address) = &config.http_redirect {
+        // redirect to the port of the first address HTTPS is served on
+        let https_port = config
+            .listen_addresses()
+            .iter()
+            .filter(|address| !address.starts_with("unix:"))
+            .find_map(|address| address.to_socket_addrs().ok()?.next())
+            .map_or(443, |addr| addr.port());
+        let redirect = std::net::TcpListener::bind(address).map_err(|e| {
+            std::io::Error::new(e.kind(), format!("can not listen on {}: {}", address, e))
+        })?;
+        let mut redirect_app = tide::new();
+        redirect_app
+            .at("/")
+            .all(move |req| redirect_to_https(req, https_port));
+        redirect_app
+            .at("*")
+            .all(move |req| redirect_to_https(req, https_port));
+        async_std::task::spawn(redirect_app.listen(redirect));
+    }
+
+    app.listen(listener).await?;
     Ok(())
 }
+
+/// Redirect a plain HTTP request to the same URL using HTTPS.
+async fn redirect_to_https(req: Request<()>, https_port: u16) -> tide::Result {
+    let mut url = req.url().clone();
+    url.set_scheme("https").unwrap();
+    url.set_port(Some(https_port).filter(|&port| port != 443))
+        .unwrap();
+    Ok(tide::Redirect::permanent(url).into())
+}
+
+/// Bind to an address of the `listen` option and add it to the listener.
+/// TCP addresses serve HTTPS if TLS is configured, Unix sockets are always
+/// plain HTTP for use behind a reverse proxy.
+fn bind(
+    address: &str,
+    config: &Config,
+    tls: Option<&futures_rustls::TlsAcceptor>,
+    listener: &mut tide::listener::ConcurrentListener<()>,
+) -> std::io::Result<()> {
+    let path = match address.strip_prefix("unix:") {
+        Some(path) => path,
+        None => {
+            let socket = std::net::TcpListener::bind(address)?;
+            return match tls {
+                Some(tls) => listener.add(tls::TlsListener::new(socket, tls.clone())),
+                None => listener.add(socket),
+            };
+        }
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // remove the socket left over from a previous run
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let socket = std::os::unix::net::UnixListener::bind(path)?;
+        // already checked when loading the configuration
+        let mode = config.socket_mode().unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        listener.add(socket)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, config, listener);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Unix sockets are not supported on this platform",
+        ))
+    }
+}
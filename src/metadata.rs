@@ -0,0 +1,177 @@
+//! In-memory cache of the metadata shown on the index page.
+//!
+//! Opening every repository on every request to the index is slow when
+//! there are many repositories. Instead the metadata is only read again when
+//! the modification times of the files it is derived from change, i.e. the
+//! refs, the configuration or the description, which is checked at most
+//! every few seconds.
+
+use crate::{filters, languages::Languages};
+use git2::{Repository, Time};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Metadata by the path of the repository, as returned by discovery, and when
+/// it was last checked to be up to date.
+type MetadataCache = HashMap<PathBuf, (Instant, Arc<RepoMetadata>)>;
+static CACHE: Lazy<Mutex<MetadataCache>> = Lazy::new(Default::default);
+
+/// Checking whether the metadata is up to date looks at every ref, so it is
+/// only done this often.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct RepoMetadata {
+    /// as returned by discovery
+    pub path: PathBuf,
+    pub name: String,
+    pub description: String,
+    pub owner: String,
+    pub category: String,
+    /// time of the last commit, `None` for empty repositories
+    pub last_modified: Option<Time>,
+    /// the branch the repository page shows, `None` for empty repositories
+    /// and a detached HEAD
+    pub default_branch: Option<String>,
+    pub languages: Arc<Languages>,
+    /// set with `mygit.hidden`, the repository is still served but not listed
+    pub hidden: bool,
+    stamp: Stamp,
+}
+
+/// Summary of the modification times of the files in the git directory that
+/// the metadata is derived from.
+#[derive(PartialEq, Default)]
+struct Stamp {
+    newest: Option<SystemTime>,
+    // so deleting a ref is noticed even if it was not the newest file
+    files: usize,
+}
+
+impl Stamp {
+    fn of(git_dir: &Path) -> Self {
+        fn visit(path: &Path, stamp: &mut Stamp) {
+            let metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(_) => return,
+            };
+            stamp.files += 1;
+            if let Ok(modified) = metadata.modified() {
+                stamp.newest = stamp.newest.max(Some(modified));
+            }
+            if metadata.is_dir() {
+                // directory modification times only change if entries are
+                // added or removed, so also look at the files inside
+                for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+                    visit(&entry.path(), stamp);
+                }
+            }
+        }
+
+        let config = crate::CONFIG.load();
+        let mut stamp = Stamp::default();
+        // owners may be given there
+        if let Some(projects_list) = &config.projects_list {
+            visit(Path::new(projects_list), &mut stamp);
+        }
+        for file in &[
+            "HEAD",
+            "config",
+            "description",
+            "packed-refs",
+            "refs",
+            &config.export_ok,
+        ] {
+            visit(&git_dir.join(file), &mut stamp);
+        }
+        stamp
+    }
+}
+
+impl RepoMetadata {
+    fn load(repo: &Repository, path: &Path, stamp: Stamp) -> askama::Result<Self> {
+        let config = crate::RepoConfig::of(repo);
+        Ok(Self {
+            path: path.to_path_buf(),
+            name: filters::repo_name(repo)?.to_string(),
+            description: filters::description(repo)?,
+            owner: filters::repo_owner(repo)?,
+            category: filters::category(repo)?,
+            last_modified: filters::last_modified(repo).ok(),
+            default_branch: Some(crate::default_ref(repo)).filter(|branch| branch != "HEAD"),
+            languages: Languages::of(repo),
+            hidden: config.hidden,
+            stamp,
+        })
+    }
+}
+
+/// The git directory of a repository, i.e. the repository itself if it is
+/// bare or its `.git` subdirectory otherwise.
+pub fn git_dir(path: &Path) -> Option<PathBuf> {
+    let dotgit = path.join(".git");
+    if dotgit.is_dir() {
+        Some(dotgit)
+    } else if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+        Some(path.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// Get the metadata of the repositories at the given paths. Repositories that
+/// can not be opened are skipped. Cached metadata of other repositories is
+/// dropped, since they are not served any more.
+pub fn for_repos(paths: &[PathBuf]) -> Vec<Arc<RepoMetadata>> {
+    // loading the metadata can take a while, so the cache is not locked
+    // meanwhile to not block other requests
+    let cached = {
+        let mut cache = CACHE.lock().unwrap();
+        cache.retain(|path, _| paths.contains(path));
+        paths
+            .iter()
+            .map(|path| cache.get(path).cloned())
+            .collect::<Vec<_>>()
+    };
+
+    let mut result = Vec::with_capacity(paths.len());
+    let mut updated = Vec::new();
+    for (path, cached) in paths.iter().zip(cached) {
+        match cached {
+            Some((checked, metadata)) if checked.elapsed() < CHECK_INTERVAL => {
+                result.push(metadata);
+                continue;
+            }
+            _ => {}
+        }
+        let stamp = match git_dir(path) {
+            Some(git_dir) => Stamp::of(&git_dir),
+            None => continue,
+        };
+        let metadata = match cached {
+            Some((_, metadata)) if metadata.stamp == stamp => metadata,
+            _ => {
+                let metadata = Repository::open(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|repo| {
+                        RepoMetadata::load(&repo, path, stamp).map_err(|e| e.to_string())
+                    });
+                match metadata {
+                    Ok(metadata) => Arc::new(metadata),
+                    Err(e) => {
+                        tide::log::warn!("can not read repository {:?}: {}", path, e);
+                        continue;
+                    }
+                }
+            }
+        };
+        updated.push((path.clone(), (Instant::now(), metadata.clone())));
+        result.push(metadata);
+    }
+
+    CACHE.lock().unwrap().extend(updated);
+    result
+}
@@ -0,0 +1,301 @@
+//! Streaming raw file contents, with support for range requests.
+//!
+//! Files are read in a blocking task and passed to the response in chunks, so
+//! large files are not copied into memory at once. Loose objects are read with
+//! a stream from the object database. libgit2 can not stream packed objects,
+//! so those are loaded into memory as a whole by the blocking task, which is
+//! the case for most blobs of a repository after `git gc`.
+
+use async_std::channel::{self, Receiver, Sender};
+use async_std::stream::Stream;
+use async_std::task;
+use git2::{Oid, Repository};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tide::http::{mime, Mime};
+use tide::{Body, Request, Response};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// like git, files with a NUL byte in the first 8000 bytes are binary
+const BINARY_CHECK_SIZE: u64 = 8000;
+
+/// Where the contents are read from.
+pub enum Source {
+    /// a blob, by the path of the repository
+    Blob(PathBuf, Oid),
+    File(PathBuf),
+}
+
+/// A single byte range of a `Range` header, with the first and last byte.
+/// Without a first byte, the last byte is the length of a suffix.
+struct ByteRange {
+    first: Option<u64>,
+    last: Option<u64>,
+}
+
+impl ByteRange {
+    /// The requested range, if the header asks for a single byte range.
+    /// Other ranges are ignored, so the whole file is sent.
+    fn of(req: &Request<()>) -> Option<Self> {
+        let header = req.header("Range")?.last().as_str();
+        let spec = header.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (first, last) = spec.split_once('-')?;
+        let parse = |value: &str| match value.trim() {
+            "" => Some(None),
+            value => value.parse().ok().map(Some),
+        };
+        let range = Self {
+            first: parse(first)?,
+            last: parse(last)?,
+        };
+        match (range.first, range.last) {
+            (None, None) => None,
+            (Some(first), Some(last)) if last < first => None,
+            _ => Some(range),
+        }
+    }
+
+    /// The part of a file with the given length that this range covers.
+    fn part(&self, len: u64) -> Part {
+        let (first, last) = match (self.first, self.last) {
+            (Some(first), _) if first >= len => return Part::Unsatisfiable,
+            (Some(first), last) => (first, last.map_or(len - 1, |last| last.min(len - 1))),
+            (None, Some(suffix)) if suffix > 0 && len > 0 => (len - suffix.min(len), len - 1),
+            _ => return Part::Unsatisfiable,
+        };
+        Part::Range(first, last - first + 1)
+    }
+}
+
+/// Which part of the file is sent.
+#[derive(Clone, Copy)]
+enum Part {
+    Whole,
+    /// the first byte and the number of bytes
+    Range(u64, u64),
+    Unsatisfiable,
+}
+
+/// What is known about the file before its contents are sent.
+struct Info {
+    len: u64,
+    binary: bool,
+    part: Part,
+}
+
+/// Reads the chunks sent by the blocking task.
+struct Chunks {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl async_std::io::Read for Chunks {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if self.pos < self.chunk.len() {
+                let len = buf.len().min(self.chunk.len() - self.pos);
+                buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+                self.pos += len;
+                return Poll::Ready(Ok(len));
+            }
+            match Pin::new(&mut self.receiver).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                // all chunks were sent
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Respond with the contents of the source, or the range of them that was
+/// requested. Without a `mime` type, the contents are sent as plain text or
+/// binary.
+pub async fn respond(req: &Request<()>, source: Source, mime: Option<Mime>) -> tide::Result {
+    let range = ByteRange::of(req);
+    let (info_sender, info) = channel::bounded(1);
+    let (chunk_sender, chunks) = channel::bounded(4);
+    task::spawn_blocking(move || {
+        if let Err(e) = read(source, range, &info_sender, &chunk_sender) {
+            let _ = task::block_on(info_sender.send(Err(e)));
+        }
+    });
+
+    let info = info
+        .recv()
+        .await
+        .map_err(|_| tide::Error::from_str(500, "The file could not be read."))??;
+    let mime = mime.unwrap_or(if info.binary {
+        mime::BYTE_STREAM
+    } else {
+        mime::PLAIN
+    });
+    let reader = async_std::io::BufReader::new(Chunks {
+        receiver: chunks,
+        chunk: Vec::new(),
+        pos: 0,
+    });
+
+    let mut response = Response::new(200);
+    response.insert_header("Accept-Ranges", "bytes");
+    let len = match info.part {
+        Part::Whole => info.len,
+        Part::Range(first, len) => {
+            response.set_status(206);
+            response.insert_header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", first, first + len - 1, info.len),
+            );
+            len
+        }
+        Part::Unsatisfiable => {
+            response.set_status(416);
+            response.insert_header("Content-Range", format!("bytes */{}", info.len));
+            return Ok(response);
+        }
+    };
+    let mut body = Body::from_reader(reader, Some(len as usize));
+    body.set_mime(mime);
+    response.set_body(body);
+    Ok(response)
+}
+
+fn other(e: git2::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Open the source and send its contents, runs in a blocking task.
+fn read(
+    source: Source,
+    range: Option<ByteRange>,
+    info: &Sender<io::Result<Info>>,
+    chunks: &Sender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    match source {
+        Source::File(path) => {
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+            send_seekable(len, file, range, info, chunks);
+        }
+        Source::Blob(path, oid) => {
+            let repo = Repository::open(path).map_err(other)?;
+            let odb = repo.odb().map_err(other)?;
+            let reader = odb.reader(oid);
+            match reader {
+                // git2 does not report the end of the stream, so it has to be
+                // limited to the size of the object
+                Ok((reader, len, _)) => {
+                    send_stream(len as u64, reader.take(len as u64), range, info, chunks)
+                }
+                // a packed object, which libgit2 can only load as a whole
+                Err(_) => {
+                    let blob = repo.find_blob(oid).map_err(other)?;
+                    let content = Cursor::new(blob.content());
+                    send_seekable(blob.size() as u64, content, range, info, chunks);
+                }
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Send the info about the contents, which needs their start to tell binary
+/// files apart. Returns that start, and the first byte and number of bytes
+/// to send if there are any.
+fn send_info(
+    len: u64,
+    reader: &mut impl Read,
+    range: Option<ByteRange>,
+    info: &Sender<io::Result<Info>>,
+) -> Option<(Vec<u8>, u64, u64)> {
+    let mut start = Vec::new();
+    if let Err(e) = reader.take(BINARY_CHECK_SIZE).read_to_end(&mut start) {
+        let _ = task::block_on(info.send(Err(e)));
+        return None;
+    }
+    let part = range.map_or(Part::Whole, |range| range.part(len));
+    let binary = start.contains(&0);
+    if task::block_on(info.send(Ok(Info { len, binary, part }))).is_err() {
+        return None;
+    }
+
+    match part {
+        Part::Whole => Some((start, 0, len)),
+        Part::Range(first, count) => Some((start, first, count)),
+        Part::Unsatisfiable => None,
+    }
+}
+
+/// Send contents that can seek to the start of the range.
+fn send_seekable(
+    len: u64,
+    mut reader: impl Read + Seek,
+    range: Option<ByteRange>,
+    info: &Sender<io::Result<Info>>,
+    chunks: &Sender<io::Result<Vec<u8>>>,
+) {
+    let (_, first, count) = match send_info(len, &mut reader, range, info) {
+        Some(part) => part,
+        None => return,
+    };
+    if let Err(e) = reader.seek(SeekFrom::Start(first)) {
+        let _ = task::block_on(chunks.send(Err(e)));
+        return;
+    }
+    send_chunks(reader.take(count), chunks);
+}
+
+/// Send contents that can only be read in order, skipping everything before
+/// the range.
+fn send_stream(
+    len: u64,
+    mut reader: impl Read,
+    range: Option<ByteRange>,
+    info: &Sender<io::Result<Info>>,
+    chunks: &Sender<io::Result<Vec<u8>>>,
+) {
+    let (start, first, count) = match send_info(len, &mut reader, range, info) {
+        Some(part) => part,
+        None => return,
+    };
+    let mut reader = Cursor::new(start).chain(reader);
+    if let Err(e) = io::copy(&mut (&mut reader).take(first), &mut io::sink()) {
+        let _ = task::block_on(chunks.send(Err(e)));
+        return;
+    }
+    send_chunks(reader.take(count), chunks);
+}
+
+fn send_chunks(mut reader: impl Read, chunks: &Sender<io::Result<Vec<u8>>>) {
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let result = match reader.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(len) => {
+                chunk.truncate(len);
+                Ok(chunk)
+            }
+            Err(e) => Err(e),
+        };
+        let failed = result.is_err();
+        // stop if the response was dropped, e.g. when the client disconnected
+        if task::block_on(chunks.send(result)).is_err() || failed {
+            return;
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Searching the files of a tree.
+//!
+//! Every tree gets a trigram index: for each sequence of three bytes, the
+//! files that contain it. The trigrams a match has to contain narrow down the
+//! files before they are searched line by line. Trigrams are taken from the
+//! ASCII lowercase contents, so the index also works when ignoring the case of
+//! ASCII literals. As trees never change, indexes are cached by the oid of the
+//! tree.
+
+use git2::{ObjectType, Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::{self, Hir, HirKind, RepetitionKind, RepetitionRange};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Indexes by the path of the repository and the oid of the tree. They can
+/// be large, so only a few are kept.
+type IndexCache = LruCache<(PathBuf, Oid), Arc<Index>>;
+static CACHE: Lazy<Mutex<IndexCache>> = Lazy::new(|| Mutex::new(LruCache::new(16)));
+
+/// larger files are neither indexed nor searched
+const MAX_FILE_SIZE: usize = 1 << 20;
+/// the number of files with matches that are shown
+const MAX_FILES: usize = 100;
+/// the number of matching lines that are shown per file
+const MAX_LINES: usize = 20;
+/// the number of lines shown before and after a matching line
+const CONTEXT: usize = 2;
+
+/// The trigram index of the text files in a tree.
+pub struct Index {
+    /// paths and blob oids of the files
+    files: Vec<(String, Oid)>,
+    /// the sorted positions in `files` of the files containing each trigram
+    trigrams: HashMap<u32, Vec<u32>>,
+}
+
+fn trigram(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0].to_ascii_lowercase()) << 16
+        | u32::from(bytes[1].to_ascii_lowercase()) << 8
+        | u32::from(bytes[2].to_ascii_lowercase())
+}
+
+impl Index {
+    /// The index of the tree, which is built if it is not cached.
+    pub fn of(repo: &Repository, tree: &Tree) -> Arc<Self> {
+        let key = (repo.path().to_path_buf(), tree.id());
+        if let Some(index) = CACHE.lock().unwrap().get(&key) {
+            return index.clone();
+        }
+        let index = Arc::new(Self::build(repo, tree));
+        CACHE.lock().unwrap().put(key, index.clone());
+        index
+    }
+
+    fn build(repo: &Repository, tree: &Tree) -> Self {
+        let mut index = Self {
+            files: Vec::new(),
+            trigrams: HashMap::new(),
+        };
+        let odb = match repo.odb() {
+            Ok(odb) => odb,
+            Err(_) => return index,
+        };
+        let walked = tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(ObjectType::Blob)
+                || entry.filemode() == crate::symlink::FILEMODE
+            {
+                // not a file or a symlink
+                return TreeWalkResult::Ok;
+            }
+            // large files are skipped before they are loaded
+            match odb.read_header(entry.id()) {
+                Ok((size, _)) if size <= MAX_FILE_SIZE => {}
+                _ => return TreeWalkResult::Ok,
+            }
+            let blob = match repo.find_blob(entry.id()) {
+                Ok(blob) => blob,
+                Err(_) => return TreeWalkResult::Ok,
+            };
+            if blob.is_binary() {
+                return TreeWalkResult::Ok;
+            }
+
+            let id = index.files.len() as u32;
+            index.files.push((
+                format!("{}{}", dir, String::from_utf8_lossy(entry.name_bytes())),
+                entry.id(),
+            ));
+            let mut trigrams = blob.content().windows(3).map(trigram).collect::<Vec<_>>();
+            trigrams.sort_unstable();
+            trigrams.dedup();
+            for trigram in trigrams {
+                index.trigrams.entry(trigram).or_default().push(id);
+            }
+            TreeWalkResult::Ok
+        });
+        if let Err(e) = walked {
+            tide::log::warn!("can not walk tree of {:?}: {}", repo.path(), e);
+        }
+        index
+    }
+
+    /// The positions of the files that contain all of the literals, all files
+    /// if none of them are long enough to use the index.
+    fn candidates(&self, literals: &[String]) -> Vec<u32> {
+        let mut trigrams = literals
+            .iter()
+            .flat_map(|literal| literal.as_bytes().windows(3).map(trigram))
+            .collect::<Vec<_>>();
+        trigrams.sort_unstable();
+        trigrams.dedup();
+
+        let mut lists = Vec::new();
+        for trigram in &trigrams {
+            match self.trigrams.get(trigram) {
+                Some(files) => lists.push(files),
+                // no file contains it
+                None => return Vec::new(),
+            }
+        }
+        // start with the shortest list, it limits the result the most
+        lists.sort_unstable_by_key(|files| files.len());
+        match lists.split_first() {
+            Some((first, rest)) => first
+                .iter()
+                .copied()
+                .filter(|id| rest.iter().all(|files| files.binary_search(id).is_ok()))
+                .collect(),
+            None => (0..self.files.len() as u32).collect(),
+        }
+    }
+}
+
+/// Literal strings that every match of the regular expression contains.
+fn required_literals(hir: &Hir, literals: &mut Vec<String>, current: &mut String) {
+    match hir.kind() {
+        HirKind::Literal(hir::Literal::Unicode(c)) => current.push(*c),
+        HirKind::Concat(hirs) => {
+            for hir in hirs {
+                required_literals(hir, literals, current);
+            }
+        }
+        HirKind::Group(group) => required_literals(&group.hir, literals, current),
+        HirKind::Repetition(repetition) => {
+            let at_least_once = match &repetition.kind {
+                RepetitionKind::OneOrMore => true,
+                RepetitionKind::Range(RepetitionRange::Exactly(n))
+                | RepetitionKind::Range(RepetitionRange::AtLeast(n))
+                | RepetitionKind::Range(RepetitionRange::Bounded(n, _)) => *n > 0,
+                RepetitionKind::ZeroOrOne | RepetitionKind::ZeroOrMore => false,
+            };
+            flush(literals, current);
+            if at_least_once {
+                required_literals(&repetition.hir, literals, current);
+                flush(literals, current);
+            }
+        }
+        // anything else can match different strings
+        _ => flush(literals, current),
+    }
+}
+
+/// End the current run of literal characters.
+fn flush(literals: &mut Vec<String>, current: &mut String) {
+    if current.len() >= 3 {
+        literals.push(current.clone());
+    }
+    current.clear();
+}
+
+/// How the query is matched.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Literal,
+    Regex,
+    /// matching paths against a glob pattern, not the contents
+    Path,
+}
+
+impl Mode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "literal" => Some(Self::Literal),
+            "regex" => Some(Self::Regex),
+            "path" => Some(Self::Path),
+            _ => None,
+        }
+    }
+}
+
+/// A part of a line, which is highlighted if it matched.
+pub struct Part {
+    pub text: String,
+    pub matched: bool,
+}
+
+pub struct Line {
+    pub number: usize,
+    pub parts: Vec<Part>,
+}
+
+/// A file with matches, and the matching lines with some context around them.
+pub struct FileMatch {
+    pub path: String,
+    /// runs of consecutive lines
+    pub snippets: Vec<Vec<Line>>,
+}
+
+pub struct Results {
+    pub files: Vec<FileMatch>,
+    /// whether more files matched than are shown
+    pub truncated: bool,
+}
+
+/// Search the files of the tree.
+pub fn search(
+    repo: &Repository,
+    tree: &Tree,
+    query: &str,
+    mode: Mode,
+    ignore_case: bool,
+    path: Option<&glob::Pattern>,
+) -> Result<Results, String> {
+    let options = glob::MatchOptions {
+        case_sensitive: !ignore_case,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+    if mode == Mode::Path {
+        let pattern = glob::Pattern::new(query).map_err(|e| e.to_string())?;
+        return Ok(search_paths(repo, tree, &pattern, options));
+    }
+
+    let (regex, mut literals) = match mode {
+        Mode::Regex => {
+            let regex = RegexBuilder::new(query)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| e.to_string())?;
+            let mut literals = Vec::new();
+            if let Ok(hir) = regex_syntax::Parser::new().parse(query) {
+                let mut current = String::new();
+                required_literals(&hir, &mut literals, &mut current);
+                flush(&mut literals, &mut current);
+            }
+            (regex, literals)
+        }
+        _ => {
+            let regex = RegexBuilder::new(&regex::escape(query))
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| e.to_string())?;
+            (regex, vec![query.to_string()])
+        }
+    };
+
+    if ignore_case {
+        // other characters may be in a different case in the index
+        literals.retain(|literal| literal.is_ascii());
+    }
+
+    let index = Index::of(repo, tree);
+    let mut results = Results {
+        files: Vec::new(),
+        truncated: false,
+    };
+    for id in index.candidates(&literals) {
+        let (file, oid) = &index.files[id as usize];
+        if path.is_some_and(|pattern| !pattern.matches_with(file, options)) {
+            continue;
+        }
+        let blob = match repo.find_blob(*oid) {
+            Ok(blob) => blob,
+            Err(_) => continue,
+        };
+        let snippets = search_lines(&String::from_utf8_lossy(blob.content()), &regex);
+        if snippets.is_empty() {
+            continue;
+        }
+        if results.files.len() == MAX_FILES {
+            results.truncated = true;
+            break;
+        }
+        results.files.push(FileMatch {
+            path: file.clone(),
+            snippets,
+        });
+    }
+    Ok(results)
+}
+
+/// Files whose path matches the pattern, without any lines.
+fn search_paths(
+    repo: &Repository,
+    tree: &Tree,
+    pattern: &glob::Pattern,
+    options: glob::MatchOptions,
+) -> Results {
+    let mut results = Results {
+        files: Vec::new(),
+        truncated: false,
+    };
+    let walked = tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Tree) {
+            return TreeWalkResult::Ok;
+        }
+        let path = format!("{}{}", dir, String::from_utf8_lossy(entry.name_bytes()));
+        if !pattern.matches_with(&path, options) {
+            return TreeWalkResult::Ok;
+        }
+        if results.files.len() == MAX_FILES {
+            results.truncated = true;
+            return TreeWalkResult::Abort;
+        }
+        results.files.push(FileMatch {
+            path,
+            snippets: Vec::new(),
+        });
+        TreeWalkResult::Ok
+    });
+    // aborting the walk is reported as an error
+    if let Err(e) = walked.map_err(|e| e.code()) {
+        if e != git2::ErrorCode::User {
+            tide::log::warn!("can not walk tree of {:?}: {:?}", repo.path(), e);
+        }
+    }
+    results
+}
+
+/// The matching lines of the text with context, in runs of consecutive lines.
+fn search_lines(text: &str, regex: &Regex) -> Vec<Vec<Line>> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let matching = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(n, _)| n)
+        .take(MAX_LINES)
+        .collect::<Vec<_>>();
+
+    let mut snippets: Vec<Vec<Line>> = Vec::new();
+    // the index of the next line that is not shown yet
+    let mut next = 0;
+    for &n in &matching {
+        let start = n.saturating_sub(CONTEXT).max(next);
+        let end = (n + CONTEXT + 1).min(lines.len());
+        // continue the last snippet if there is no gap
+        if start != next || snippets.is_empty() {
+            snippets.push(Vec::new());
+        }
+        let snippet = snippets.last_mut().unwrap();
+        for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+            let parts = if matching.contains(&i) {
+                highlight(line, regex)
+            } else {
+                vec![Part {
+                    text: line.to_string(),
+                    matched: false,
+                }]
+            };
+            snippet.push(Line {
+                number: i + 1,
+                parts,
+            });
+        }
+        next = end.max(next);
+    }
+    snippets
+}
+
+/// Split the line into the parts that matched and those that did not.
+fn highlight(line: &str, regex: &Regex) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut last = 0;
+    for found in regex
+        .find_iter(line)
+        .filter(|found| !found.as_str().is_empty())
+    {
+        parts.push(Part {
+            text: line[last..found.start()].to_string(),
+            matched: false,
+        });
+        parts.push(Part {
+            text: found.as_str().to_string(),
+            matched: true,
+        });
+        last = found.end();
+    }
+    parts.push(Part {
+        text: line[last..].to_string(),
+        matched: false,
+    });
+    parts
+}
@@ -0,0 +1,255 @@
+//! Searching commits and files across all repositories from the index page.
+//!
+//! A background thread keeps an index of the commit messages and file paths
+//! at the default branch of every discovered repository. It is updated
+//! incrementally: repositories whose default branch did not move are skipped,
+//! and if the branch only moved forward, just the new commits are read.
+
+use crate::metadata::RepoMetadata;
+use git2::{Oid, Repository, Time, TreeWalkMode, TreeWalkResult};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// how long to wait between updates of the index
+const INTERVAL: Duration = Duration::from_secs(60);
+/// the number of commits and files that are shown each
+const MAX_RESULTS: usize = 50;
+
+static INDEX: Lazy<Mutex<Index>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct Index {
+    /// by the path of the repository, as returned by discovery
+    repos: HashMap<PathBuf, Arc<RepoIndex>>,
+    /// whether all repositories were indexed at least once
+    complete: bool,
+}
+
+struct RepoIndex {
+    metadata: Arc<RepoMetadata>,
+    /// the branch that was indexed and the commit it pointed to
+    branch: String,
+    head: Oid,
+    contents: Arc<Contents>,
+}
+
+struct Contents {
+    /// newest first
+    commits: Vec<IndexedCommit>,
+    paths: Vec<IndexedPath>,
+}
+
+#[derive(Clone)]
+struct IndexedCommit {
+    id: Oid,
+    message: String,
+    /// the message in lowercase, so searches do not have to convert it
+    lowercase: String,
+    time: Time,
+}
+
+struct IndexedPath {
+    path: String,
+    /// the path in lowercase, so searches do not have to convert it
+    lowercase: String,
+}
+
+/// Keep the index up to date, runs in its own thread.
+pub fn update_forever() {
+    loop {
+        update();
+        std::thread::sleep(INTERVAL);
+    }
+}
+
+fn update() {
+    let repos = crate::metadata::for_repos(&crate::discover_repos());
+    let paths = repos
+        .iter()
+        .map(|repo| repo.path.clone())
+        .collect::<Vec<_>>();
+    INDEX
+        .lock()
+        .unwrap()
+        .repos
+        .retain(|path, _| paths.contains(path));
+
+    for metadata in repos {
+        let old = INDEX.lock().unwrap().repos.get(&metadata.path).cloned();
+        // the index is not locked while reading the repository, so searches
+        // do not have to wait
+        match index_repo(metadata.clone(), old.as_deref()) {
+            Ok(Some(indexed)) => {
+                INDEX
+                    .lock()
+                    .unwrap()
+                    .repos
+                    .insert(metadata.path.clone(), Arc::new(indexed));
+            }
+            Ok(None) => {
+                INDEX.lock().unwrap().repos.remove(&metadata.path);
+            }
+            Err(e) => tide::log::warn!("can not index repository {:?}: {}", metadata.path, e),
+        }
+    }
+    INDEX.lock().unwrap().complete = true;
+}
+
+/// Index the repository, reusing what is still up to date in the old index.
+/// Empty repositories are not indexed.
+fn index_repo(
+    metadata: Arc<RepoMetadata>,
+    old: Option<&RepoIndex>,
+) -> Result<Option<RepoIndex>, git2::Error> {
+    let repo = Repository::open(&metadata.path)?;
+    let branch = crate::default_ref(&repo);
+    let head = match repo
+        .revparse_single(&branch)
+        .and_then(|head| head.peel_to_commit())
+    {
+        Ok(head) => head,
+        Err(_) => return Ok(None),
+    };
+
+    let old = old.filter(|old| old.branch == branch);
+    if let Some(old) = old.filter(|old| old.head == head.id()) {
+        // only the name, description or visibility may have changed
+        return Ok(Some(RepoIndex {
+            metadata,
+            branch,
+            head: old.head,
+            contents: old.contents.clone(),
+        }));
+    }
+    // only the new commits have to be read if the branch moved forward
+    let old = old.filter(|old| {
+        repo.graph_descendant_of(head.id(), old.head)
+            .unwrap_or(false)
+    });
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head.id())?;
+    if let Some(old) = old {
+        revwalk.hide(old.head)?;
+    }
+    revwalk.set_sorting(git2::Sort::TIME)?;
+    let mut commits = revwalk
+        .filter_map(|oid| repo.find_commit(oid.ok()?).ok())
+        .map(|commit| {
+            let message = commit.message().unwrap_or("").to_string();
+            IndexedCommit {
+                id: commit.id(),
+                lowercase: message.to_lowercase(),
+                message,
+                time: commit.time(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if let Some(old) = old {
+        commits.extend(old.contents.commits.iter().cloned());
+    }
+
+    let mut paths = Vec::new();
+    head.tree()?.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() != Some(git2::ObjectType::Tree) {
+            let path = format!("{}{}", dir, String::from_utf8_lossy(entry.name_bytes()));
+            paths.push(IndexedPath {
+                lowercase: path.to_lowercase(),
+                path,
+            });
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    Ok(Some(RepoIndex {
+        metadata,
+        branch,
+        head: head.id(),
+        contents: Arc::new(Contents { commits, paths }),
+    }))
+}
+
+pub struct CommitMatch {
+    pub repo: String,
+    pub id: String,
+    pub summary: String,
+    pub time: Time,
+}
+
+pub struct FileMatch {
+    pub repo: String,
+    pub branch: String,
+    pub path: String,
+}
+
+pub struct Results {
+    /// newest first
+    pub commits: Vec<CommitMatch>,
+    pub files: Vec<FileMatch>,
+    /// whether more commits or files matched than are shown
+    pub truncated: bool,
+    /// whether some repositories are not indexed yet
+    pub incomplete: bool,
+}
+
+/// Find the commits with the query in their message and the files with the
+/// query in their path, ignoring case. Hidden repositories are not searched.
+pub fn search(query: &str) -> Results {
+    let query = query.to_lowercase();
+    // searching takes a while, the index should not be locked meanwhile
+    let (mut repos, complete) = {
+        let index = INDEX.lock().unwrap();
+        let repos = index
+            .repos
+            .values()
+            .filter(|repo| !repo.metadata.hidden)
+            .cloned()
+            .collect::<Vec<_>>();
+        (repos, index.complete)
+    };
+    let mut results = Results {
+        commits: Vec::new(),
+        files: Vec::new(),
+        truncated: false,
+        incomplete: !complete,
+    };
+
+    repos.sort_by(|a, b| a.metadata.name.cmp(&b.metadata.name));
+    for repo in repos {
+        let name = &repo.metadata.name;
+        results.commits.extend(
+            repo.contents
+                .commits
+                .iter()
+                .filter(|commit| commit.lowercase.contains(&query))
+                .map(|commit| CommitMatch {
+                    repo: name.clone(),
+                    id: commit.id.to_string(),
+                    summary: commit.message.lines().next().unwrap_or("").to_string(),
+                    time: commit.time,
+                }),
+        );
+        results.files.extend(
+            repo.contents
+                .paths
+                .iter()
+                .filter(|path| path.lowercase.contains(&query))
+                .map(|path| FileMatch {
+                    repo: name.clone(),
+                    branch: repo.branch.clone(),
+                    path: path.path.clone(),
+                }),
+        );
+    }
+
+    results
+        .commits
+        .sort_by_key(|commit| std::cmp::Reverse(commit.time.seconds()));
+    results.truncated = results.commits.len() > MAX_RESULTS || results.files.len() > MAX_RESULTS;
+    results.commits.truncate(MAX_RESULTS);
+    results.files.truncate(MAX_RESULTS);
+    results
+}
@@ -0,0 +1,138 @@
+//! Submodules, i.e. tree entries that pin a commit of another repository.
+//!
+//! Their URLs are read from the `.gitmodules` file of the same commit. If a
+//! URL points to another repository served by this instance, the entry links
+//! to the pinned commit there instead.
+
+use git2::{Oid, Repository, Tree};
+use std::path::{Path, PathBuf};
+
+/// file mode of submodule entries in trees
+pub const FILEMODE: i32 = 0o160000;
+
+/// A submodule as configured in `.gitmodules`.
+pub struct Submodule {
+    pub path: PathBuf,
+    pub url: String,
+}
+
+/// A submodule entry of a tree, for displaying it.
+pub struct SubmoduleEntry {
+    /// the pinned commit of the other repository
+    pub commit: Oid,
+    pub url: Option<String>,
+    /// where the entry links to, if it can be shown in a browser
+    pub link: Option<String>,
+}
+
+impl SubmoduleEntry {
+    pub fn short_id(&self) -> String {
+        self.commit.to_string()[..7].to_string()
+    }
+
+    /// the URL from `.gitmodules`, empty if it is not configured there
+    pub fn url(&self) -> &str {
+        self.url.as_deref().unwrap_or_default()
+    }
+}
+
+/// Read the submodules from the `.gitmodules` file in the root of the tree.
+pub fn gitmodules(repo: &Repository, tree: &Tree) -> Vec<Submodule> {
+    let blob = match tree
+        .get_name(".gitmodules")
+        .and_then(|entry| repo.find_blob(entry.id()).ok())
+    {
+        Some(blob) => blob,
+        None => return Vec::new(),
+    };
+    let text = String::from_utf8_lossy(blob.content());
+
+    // the file uses the git config syntax, only the simple cases are
+    // handled: [submodule "name"] sections with path and url keys
+    let mut submodules = Vec::new();
+    let mut current: (Option<String>, Option<String>) = (None, None);
+    let mut finish = |(path, url): &mut (Option<String>, Option<String>)| {
+        if let (Some(path), Some(url)) = (path.take(), url.take()) {
+            submodules.push(Submodule {
+                path: path.into(),
+                url,
+            });
+        }
+    };
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            finish(&mut current);
+        } else if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "path" => current.0 = Some(value),
+                "url" => current.1 = Some(value),
+                _ => {}
+            }
+        }
+    }
+    finish(&mut current);
+    submodules
+}
+
+/// Describe the submodule at `path` that pins `commit`.
+pub fn entry(
+    repo: &Repository,
+    submodules: &[Submodule],
+    path: &Path,
+    commit: Oid,
+) -> SubmoduleEntry {
+    let url = submodules
+        .iter()
+        .find(|submodule| submodule.path == path)
+        .map(|submodule| submodule.url.clone());
+    let link = url.as_deref().and_then(|url| match local_repo(repo, url) {
+        Some(name) => Some(format!("/{}/tree/{}", name, commit)),
+        None if url.starts_with("https://") || url.starts_with("http://") => Some(url.to_string()),
+        // e.g. ssh or git URLs, which browsers can not open
+        None => None,
+    });
+    SubmoduleEntry { commit, url, link }
+}
+
+/// The name of the repository served by this instance that the URL points
+/// to, if any.
+fn local_repo(repo: &Repository, url: &str) -> Option<String> {
+    let repo_name = crate::filters::repo_name(repo).ok()?;
+
+    let name = if url.starts_with("./") || url.starts_with("../") {
+        // relative to the URL of this repository
+        let mut segments = repo_name.split('/').collect::<Vec<_>>();
+        for segment in url.split('/') {
+            match segment {
+                "." | "" => {}
+                ".." => {
+                    segments.pop()?;
+                }
+                segment => segments.push(segment),
+            }
+        }
+        segments.join("/")
+    } else {
+        // matches one of the URLs this instance shows for cloning
+        let config = crate::CONFIG.load();
+        let clone_base = format!("{}/{{repo}}", config.clone_base);
+        config
+            .clone_urls
+            .iter()
+            .map(|clone_url| clone_url.url.as_str())
+            .chain(Some(clone_base.as_str()).filter(|_| !config.clone_base.is_empty()))
+            .find_map(|template| {
+                let (prefix, suffix) = template.split_once("{repo}")?;
+                url.strip_prefix(prefix)?
+                    .strip_suffix(suffix)
+                    .map(|name| name.trim_matches('/').to_string())
+            })?
+    };
+
+    // the URL might leave out the .git suffix of bare repositories
+    [name.clone(), format!("{}.git", name)]
+        .iter()
+        .find(|name| !name.is_empty() && crate::repo_from_request(name).is_ok())
+        .cloned()
+}
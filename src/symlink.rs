@@ -0,0 +1,119 @@
+//! Symbolic links, i.e. tree entries whose content is the path they point to.
+//!
+//! Targets are resolved within the tree of the same commit, following other
+//! links on the way like the file system would.
+
+use git2::{Repository, Tree};
+use std::collections::VecDeque;
+use std::path::{Component, Path, PathBuf};
+
+/// file mode of symbolic link entries in trees
+pub const FILEMODE: i32 = 0o120000;
+/// how many links are followed before giving up, like `MAXSYMLINKS` on Linux
+const MAX_LINKS: usize = 40;
+
+/// Where a symbolic link points to.
+pub enum Target {
+    /// a path in the tree, which is empty for its root
+    Inside(PathBuf),
+    /// an absolute path or one above the root of the tree
+    Outside,
+    /// a path that does not exist in the tree
+    Dangling,
+}
+
+/// A symbolic link entry of a tree, for displaying it.
+pub struct Symlink {
+    /// the path as it is written in the link
+    pub target: String,
+    pub resolved: Target,
+}
+
+impl Symlink {
+    /// The path the link resolves to in the tree, if it does.
+    pub fn path(&self) -> Option<String> {
+        match &self.resolved {
+            Target::Inside(path) => Some(path.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    /// Why the target can not be shown, empty if it can.
+    pub fn problem(&self) -> &'static str {
+        match self.resolved {
+            Target::Inside(_) => "",
+            Target::Outside => "outside of the repository",
+            Target::Dangling => "dangling link",
+        }
+    }
+}
+
+/// The target written in the link at `path`, if it is one.
+fn read_target(repo: &Repository, tree: &Tree, path: &Path) -> Option<String> {
+    let entry = tree.get_path(path).ok()?;
+    if entry.filemode() != FILEMODE {
+        return None;
+    }
+    let blob = repo.find_blob(entry.id()).ok()?;
+    Some(String::from_utf8_lossy(blob.content()).into_owned())
+}
+
+/// Describe the symbolic link at `path` in the root `tree` of a commit.
+pub fn entry(repo: &Repository, tree: &Tree, path: &Path) -> Option<Symlink> {
+    let target = read_target(repo, tree, path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Some(Symlink {
+        resolved: resolve(repo, tree, dir, &target),
+        target,
+    })
+}
+
+/// Resolve `target` relative to the directory `dir` in the tree.
+fn resolve(repo: &Repository, tree: &Tree, dir: &Path, target: &str) -> Target {
+    if target.starts_with('/') {
+        return Target::Outside;
+    }
+
+    let mut resolved = dir
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut remaining = target.split('/').map(String::from).collect::<VecDeque<_>>();
+    let mut links = 0;
+
+    while let Some(name) = remaining.pop_front() {
+        match name.as_str() {
+            "" | "." => {}
+            ".." => {
+                if resolved.pop().is_none() {
+                    return Target::Outside;
+                }
+            }
+            _ => {
+                resolved.push(name);
+                let path = resolved.iter().collect::<PathBuf>();
+                if tree.get_path(&path).is_err() {
+                    return Target::Dangling;
+                }
+                // continue with the target of a link on the way
+                if let Some(target) = read_target(repo, tree, &path) {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return Target::Dangling;
+                    }
+                    if target.starts_with('/') {
+                        return Target::Outside;
+                    }
+                    resolved.pop();
+                    for name in target.split('/').rev() {
+                        remaining.push_front(name.to_string());
+                    }
+                }
+            }
+        }
+    }
+    Target::Inside(resolved.iter().collect())
+}
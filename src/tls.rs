@@ -0,0 +1,271 @@
+//! Serving HTTPS directly, for deployments without a reverse proxy.
+//!
+//! The certificate and key are read again when their files change, so
+//! renewed certificates are used without restarting the server.
+
+use async_std::io::{self, Read, Write};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use async_std::task;
+use futures_rustls::rustls::{
+    self,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use futures_rustls::{server::TlsStream, TlsAcceptor};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tide::listener::{ListenInfo, Listener, ToListener};
+use tide::Server;
+
+/// Read the certificate chain and private key from PEM files.
+pub fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("can not read {:?}: {}", path, e))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+        .map_err(|e| format!("can not read certificates from {:?}: {}", cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in {:?}", cert_path));
+    }
+
+    let key = rustls_pemfile::read_all(&mut open(key_path)?)
+        .map_err(|e| format!("can not read private key from {:?}: {}", key_path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {:?}", key_path))?;
+    let key = rustls::sign::any_supported_type(&rustls::PrivateKey(key))
+        .map_err(|_| format!("unsupported private key in {:?}", key_path))?;
+
+    Ok(CertifiedKey::new(
+        certs.into_iter().map(rustls::Certificate).collect(),
+        key,
+    ))
+}
+
+/// The certificate and key files and their modification times, to notice
+/// when they have to be read again.
+#[derive(PartialEq)]
+struct Stamp {
+    cert_path: String,
+    key_path: String,
+    cert_modified: Option<SystemTime>,
+    key_modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn of(cert_path: &str, key_path: &str) -> Self {
+        let modified = |path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        Self {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            cert_modified: modified(cert_path),
+            key_modified: modified(key_path),
+        }
+    }
+}
+
+/// Uses the certificate from the configured files, which are read again
+/// before a handshake if they changed.
+struct CertResolver {
+    current: Mutex<(Stamp, Arc<CertifiedKey>)>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let mut current = self.current.lock().unwrap();
+
+        let config = crate::CONFIG.load();
+        // if TLS was disabled, keep using the old certificate until restarted
+        if let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) {
+            let stamp = Stamp::of(cert_path, key_path);
+            if current.0 != stamp {
+                match load_certified_key(cert_path, key_path) {
+                    Ok(key) => {
+                        tide::log::info!("reloaded TLS certificate from {:?}", cert_path);
+                        current.1 = Arc::new(key);
+                    }
+                    // the files might still be written, try again when they change
+                    Err(e) => tide::log::error!("keeping the old TLS certificate: {}", e),
+                }
+                current.0 = stamp;
+            }
+        }
+
+        Some(current.1.clone())
+    }
+}
+
+/// The TLS configuration for the certificate and key in the configuration.
+pub fn acceptor() -> Result<TlsAcceptor, String> {
+    let config = crate::CONFIG.load();
+    let (cert_path, key_path) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        _ => return Err("tls_cert and tls_key are not set".to_string()),
+    };
+    let stamp = Stamp::of(cert_path, key_path);
+    let key = load_certified_key(cert_path, key_path)?;
+
+    let tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(CertResolver {
+            current: Mutex::new((stamp, Arc::new(key))),
+        }));
+    Ok(TlsAcceptor::from(Arc::new(tls_config)))
+}
+
+/// A TLS stream that can be cloned, because async-h1 reads and writes using
+/// separate handles.
+#[derive(Clone)]
+struct SharedStream(Arc<Mutex<TlsStream<TcpStream>>>);
+
+impl Read for SharedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl Write for SharedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_close(cx)
+    }
+}
+
+/// Listener that serves HTTPS on a bound TCP socket.
+pub struct TlsListener {
+    listener: Option<std::net::TcpListener>,
+    acceptor: TlsAcceptor,
+    server: Option<Server<()>>,
+    info: Option<ListenInfo>,
+}
+
+impl TlsListener {
+    pub fn new(listener: std::net::TcpListener, acceptor: TlsAcceptor) -> Self {
+        Self {
+            listener: Some(listener),
+            acceptor,
+            server: None,
+            info: None,
+        }
+    }
+}
+
+impl ToListener<()> for TlsListener {
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self> {
+        Ok(self)
+    }
+}
+
+fn handle_tls(server: Server<()>, acceptor: TlsAcceptor, stream: TcpStream) {
+    task::spawn(async move {
+        let local_addr = stream.local_addr().ok();
+        let peer_addr = stream.peer_addr().ok();
+
+        let stream = match acceptor.accept(stream).await {
+            Ok(stream) => SharedStream(Arc::new(Mutex::new(stream))),
+            Err(e) => {
+                tide::log::debug!("TLS handshake failed: {}", e);
+                return;
+            }
+        };
+
+        let result = async_h1::accept(stream, |mut req| async {
+            req.set_local_addr(local_addr);
+            req.set_peer_addr(peer_addr);
+            // so absolute URLs, e.g. in feeds, use the right scheme
+            req.url_mut().set_scheme("https").unwrap();
+            server.respond(req).await
+        })
+        .await;
+        if let Err(e) = result {
+            tide::log::error!("async-h1 error: {}", e);
+        }
+    });
+}
+
+#[async_trait::async_trait]
+impl Listener<()> for TlsListener {
+    async fn bind(&mut self, server: Server<()>) -> io::Result<()> {
+        self.server = Some(server);
+        self.info = Some(ListenInfo::new(self.to_string(), "tcp".to_string(), true));
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let server = self
+            .server
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+        let listener = async_std::net::TcpListener::from(
+            self.listener
+                .take()
+                .expect("`Listener::accept` should only be called once"),
+        );
+
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => handle_tls(server.clone(), self.acceptor.clone(), stream),
+                Err(e) => {
+                    let delay = std::time::Duration::from_millis(500);
+                    tide::log::error!("Error: {}. Pausing for {:?}.", e, delay);
+                    task::sleep(delay).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.info.iter().cloned().collect()
+    }
+}
+
+impl fmt::Debug for TlsListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsListener")
+            .field("listener", &self.listener)
+            .finish()
+    }
+}
+
+impl fmt::Display for TlsListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.listener.as_ref().and_then(|l| l.local_addr().ok()) {
+            Some(addr) => write!(f, "https://{}", addr),
+            None => write!(f, "https"),
+        }
+    }
+}
@@ -0,0 +1,16 @@
+// Show the copy buttons next to the clone URLs. Without JavaScript they stay
+// hidden, and the URLs can still be selected with a single click.
+document.querySelectorAll(".copy-button").forEach(function (button) {
+  if (!navigator.clipboard) {
+    return;
+  }
+  button.hidden = false;
+  button.addEventListener("click", function () {
+    navigator.clipboard.writeText(button.dataset.copy).then(function () {
+      button.textContent = "copied";
+      setTimeout(function () {
+        button.textContent = "copy";
+      }, 2000);
+    });
+  });
+});
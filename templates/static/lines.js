@@ -0,0 +1,63 @@
+// Highlight the lines selected with an anchor like #L10 or #L10-L25. Clicking
+// a line number selects it, shift-clicking selects the range from the first
+// selected line. Without JavaScript, single lines can still be linked to.
+(function () {
+  var highlight = document.createElement("div");
+  highlight.className = "line-highlight";
+  var first = null;
+
+  // the first and last selected line, or null
+  function selection() {
+    var match = /^#L(\d+)(?:-L(\d+))?$/.exec(location.hash);
+    if (!match) {
+      return null;
+    }
+    var start = parseInt(match[1], 10);
+    var end = match[2] ? parseInt(match[2], 10) : start;
+    return [Math.min(start, end), Math.max(start, end)];
+  }
+
+  function update(scroll) {
+    var range = selection();
+    var start = range && document.getElementById("L" + range[0]);
+    if (!start) {
+      highlight.remove();
+      first = null;
+      return;
+    }
+    // the range might go beyond the shown lines
+    var lines = document.querySelectorAll("a.line");
+    var end = document.getElementById("L" + range[1]) || lines[lines.length - 1];
+    var pre = start.closest("pre");
+
+    highlight.style.top = start.offsetTop + "px";
+    highlight.style.height = end.offsetTop + end.offsetHeight - start.offsetTop + "px";
+    highlight.style.width = pre.scrollWidth + "px";
+    pre.appendChild(highlight);
+    first = range[0];
+    if (scroll) {
+      start.scrollIntoView();
+    }
+  }
+
+  document.querySelectorAll("a.line").forEach(function (link) {
+    link.addEventListener("click", function (event) {
+      var line = parseInt(link.id.slice(1), 10);
+      var hash = "#L" + line;
+      if (event.shiftKey && first !== null && first !== line) {
+        hash = "#L" + Math.min(first, line) + "-L" + Math.max(first, line);
+      }
+      event.preventDefault();
+      // the links point to the file at the commit, so this is a permalink
+      location.href = link.href.split("#")[0] + hash;
+    });
+  });
+
+  window.addEventListener("hashchange", function () {
+    update(true);
+  });
+  window.addEventListener("resize", function () {
+    update(false);
+  });
+  update(true);
+})();